@@ -0,0 +1,515 @@
+// Layout of the durable history log under `history/`:
+//
+//   LATEST              active file, appended to by `record`
+//   segments/<name>     immutable files rotated out of LATEST, optionally compressed
+//   MANIFEST            ordered list of segments with their sha256 and entry counts
+//
+// Rotated segments are named after their sequence number (`000007.log`), compacted
+// ones after the range they replaced (`000001-000006.log.gz`). MANIFEST is the
+// commit point for both operations, files missing from it are never read.
+
+use std::convert::From;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use super::crypto::Key;
+use super::dates;
+use super::history::{self, Encoder, Entry};
+
+/// LATEST is rotated once it grows past this size, or when an entry from a later month is appended
+const ROTATE_SIZE: u64 = 8 * 1024 * 1024;
+const MANIFEST_VERSION: u32 = 1;
+
+pub struct ArchiveError {
+    pub cause: String,
+}
+
+impl From<std::io::Error> for ArchiveError {
+    fn from(err: std::io::Error) -> Self {
+        ArchiveError{ cause: format!("IO Error: {}", err) }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn parse(name: &str) -> Result<Self, ArchiveError> {
+        match name {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(ArchiveError{ cause: format!("Unknown compression '{}', expected none, gzip or zstd", name) }),
+        }
+    }
+
+    fn of(name: &str) -> Self {
+        if name.ends_with(".gz") {
+            Compression::Gzip
+        } else if name.ends_with(".zst") {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "log",
+            Compression::Gzip => "log.gz",
+            Compression::Zstd => "log.zst",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Segment {
+    pub name: String,
+    pub first: u32,
+    pub last: u32,
+    pub sha256: String,
+    pub entries: usize,
+}
+
+#[derive(Default)]
+pub struct Manifest {
+    pub segments: Vec<Segment>,
+}
+
+pub struct CompactReport {
+    pub merged: Vec<String>,
+    pub segment: Option<Segment>,
+}
+
+pub fn latest_path(home: &Path) -> PathBuf {
+    home.join("history").join("LATEST")
+}
+
+fn segments_dir(home: &Path) -> PathBuf {
+    home.join("history").join("segments")
+}
+
+fn manifest_path(home: &Path) -> PathBuf {
+    home.join("history").join("MANIFEST")
+}
+
+/// Held exclusively while LATEST, the segments or MANIFEST are being replaced and shared
+/// while appending to LATEST, released on drop
+struct Lock {
+    _file: File,
+}
+
+fn lock(home: &Path, operation: libc::c_int) -> Result<Lock, ArchiveError> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(home.join("history").join("LOCK"))?;
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(Lock{ _file: file })
+}
+
+/// Parses `000007.log` or `000001-000006.log.gz` into its sequence range
+fn sequence_range(name: &str) -> Option<(u32, u32)> {
+    let stem = name.split('.').next()?;
+    match stem.split_once('-') {
+        Some((first, last)) => Some((first.parse().ok()?, last.parse().ok()?)),
+        None => stem.parse().ok().map(|seq| (seq, seq)),
+    }
+}
+
+fn segment_name(first: u32, last: u32, compression: Compression) -> String {
+    if first == last {
+        format!("{:06}.{}", first, compression.extension())
+    } else {
+        format!("{:06}-{:06}.{}", first, last, compression.extension())
+    }
+}
+
+fn parse_segment(line: &str) -> Option<Segment> {
+    let fields: Vec<&str> = line.split(':').collect();
+    if fields.len() != 3 {
+        return None;
+    }
+    let (first, last) = sequence_range(fields[0])?;
+    Some(Segment{
+        name: fields[0].to_owned(),
+        first,
+        last,
+        sha256: fields[1].to_owned(),
+        entries: fields[2].parse().ok()?,
+    })
+}
+
+pub fn load_manifest(home: &Path) -> Result<Manifest, ArchiveError> {
+    let path = manifest_path(home);
+    if !path.exists() {
+        return Ok(Manifest::default());
+    }
+
+    let mut manifest = Manifest::default();
+    let reader = BufReader::new(File::open(&path)?);
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if number == 0 {
+            if line != format!("version={}", MANIFEST_VERSION) {
+                return Err(ArchiveError{ cause: format!("Unsupported MANIFEST header '{}'", line) });
+            }
+        } else if number > 1 && !line.is_empty() {
+            let segment = parse_segment(&line).ok_or(ArchiveError{
+                cause: format!("{}:{}: malformed segment entry", path.display(), number + 1),
+            })?;
+            manifest.segments.push(segment);
+        }
+    }
+    manifest.segments.sort_by_key(|s| s.first);
+    Ok(manifest)
+}
+
+/// Writes to a temporary file first so readers never observe a partial MANIFEST
+fn save_manifest(home: &Path, manifest: &Manifest) -> Result<(), ArchiveError> {
+    let path = manifest_path(home);
+    let staging = path.with_extension("tmp");
+
+    let mut file = File::create(&staging)?;
+    write!(file, "version={}\n---\n", MANIFEST_VERSION)?;
+    for segment in manifest.segments.iter() {
+        writeln!(file, "{}:{}:{}", segment.name, segment.sha256, segment.entries)?;
+    }
+    file.sync_all()?;
+    std::fs::rename(staging, path)?;
+    Ok(())
+}
+
+fn checksum(path: &Path) -> Result<String, ArchiveError> {
+    let mut hasher = Sha256::new();
+    let mut file = File::open(path)?;
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn open_reader(path: &Path) -> Result<Box<dyn BufRead>, ArchiveError> {
+    let file = File::open(path)?;
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    Ok(match Compression::of(&name) {
+        Compression::None => Box::new(BufReader::new(file)),
+        Compression::Gzip => Box::new(BufReader::new(flate2::read::GzDecoder::new(file))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::new(file)?)),
+    })
+}
+
+pub fn read_file(path: &Path, key: Option<&Key>) -> Result<history::Archive, ArchiveError> {
+    Ok(history::read_archive(open_reader(path)?, key)?)
+}
+
+/// Every archive file in the order its entries were written, segments first then LATEST
+pub fn archive_files(home: &Path) -> Result<Vec<PathBuf>, ArchiveError> {
+    let mut files: Vec<PathBuf> = load_manifest(home)?.segments.iter()
+        .map(|s| segments_dir(home).join(&s.name))
+        .collect();
+
+    let latest = latest_path(home);
+    if latest.exists() {
+        files.push(latest);
+    }
+    Ok(files)
+}
+
+fn describe(home: &Path, name: String, first: u32, last: u32, key: Option<&Key>) -> Result<Segment, ArchiveError> {
+    let path = segments_dir(home).join(&name);
+    let archive = read_file(&path, key)?;
+    Ok(Segment{ name, first, last, sha256: checksum(&path)?, entries: archive.entries.len() })
+}
+
+/// A rotation interrupted between the rename and the MANIFEST update leaves a segment
+/// newer than everything in MANIFEST. Older unlisted files are leftovers of a compaction.
+fn adopt_orphans(home: &Path, manifest: &mut Manifest, key: Option<&Key>) -> Result<bool, ArchiveError> {
+    let dir = segments_dir(home);
+    if !dir.exists() {
+        return Ok(false);
+    }
+
+    let newest = manifest.segments.iter().map(|s| s.last).max().unwrap_or(0);
+    let mut adopted = false;
+    for file in std::fs::read_dir(dir)? {
+        let name = file?.file_name().to_string_lossy().into_owned();
+        if let Some((first, last)) = sequence_range(&name) {
+            if first > newest && !manifest.segments.iter().any(|s| s.name == name) {
+                log::warn!("Adopting archive segment '{}' missing from MANIFEST", name);
+                manifest.segments.push(describe(home, name, first, last, key)?);
+                adopted = true;
+            }
+        }
+    }
+    manifest.segments.sort_by_key(|s| s.first);
+    Ok(adopted)
+}
+
+fn year_month(timestamp: u32) -> (i64, u32) {
+    let (year, month, _) = dates::civil(timestamp);
+    (year, month)
+}
+
+/// Reads just enough of LATEST to find its format version, encoder and first entry
+fn latest_summary(path: &Path, key: Option<&Key>) -> Result<(u32, Encoder, Option<Entry>), ArchiveError> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let (version, encoder) = match lines.next() {
+        Some(line) => history::parse_header(&line?).unwrap_or((0, Encoder::Base64)),
+        None => return Ok((history::VERSION, Encoder::of(key), None)),
+    };
+    let first = lines.nth(1).transpose()?.and_then(|line| history::decode(&line, encoder, key).ok());
+    Ok((version, encoder, first))
+}
+
+/// `next` is the timestamp of the entry about to be appended. Months are compared between
+/// entries rather than with the clock, so backdated entries never start a segment of their own.
+fn should_rotate(path: &Path, next: Option<u32>, key: Option<&Key>) -> Result<bool, ArchiveError> {
+    if !path.exists() {
+        return Ok(false);
+    }
+
+    let (version, encoder, first) = latest_summary(path, key)?;
+    let first = match first {
+        Some(first) => first,
+        None => return Ok(false),
+    };
+    Ok(version != history::VERSION
+        || encoder != Encoder::of(key)
+        || path.metadata()?.len() >= ROTATE_SIZE
+        || next.map(|next| year_month(next) > year_month(first.timestamp)).unwrap_or(false))
+}
+
+/// Moves LATEST into the next numbered segment when it is too large, when `next` is from a
+/// later month than its first entry, or when it was written in an older format or encoding.
+/// The caller recreates LATEST afterwards.
+pub fn rotate(home: &Path, next: Option<u32>, key: Option<&Key>) -> Result<Option<Segment>, ArchiveError> {
+    let latest = latest_path(home);
+    if !should_rotate(&latest, next, key)? {
+        return Ok(None);
+    }
+
+    let _lock = lock(home, libc::LOCK_EX)?;
+    let mut manifest = load_manifest(home)?;
+    let adopted = adopt_orphans(home, &mut manifest, key)?;
+    // another shell may have rotated while we waited on the lock
+    if !should_rotate(&latest, next, key)? {
+        if adopted {
+            save_manifest(home, &manifest)?;
+        }
+        return Ok(None);
+    }
+
+    std::fs::create_dir_all(segments_dir(home))?;
+    let seq = manifest.segments.iter().map(|s| s.last).max().unwrap_or(0) + 1;
+    let name = segment_name(seq, seq, Compression::None);
+    std::fs::rename(&latest, segments_dir(home).join(&name))?;
+
+    let segment = describe(home, name, seq, seq, key)?;
+    manifest.segments.push(segment.clone());
+    save_manifest(home, &manifest)?;
+    log::info!("Rotated LATEST into archive segment '{}' ({} entries)", segment.name, segment.entries);
+    Ok(Some(segment))
+}
+
+/// Opens LATEST for appending, creating it with a header when it does not exist
+pub fn open_latest(home: &Path, key: Option<&Key>) -> Result<File, ArchiveError> {
+    let latest = latest_path(home);
+    match OpenOptions::new().append(true).create_new(true).open(&latest) {
+        Ok(mut file) => {
+            file.write_all(history::header(key).as_bytes())?;
+            Ok(file)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(OpenOptions::new().append(true).open(&latest)?),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Appends `line`, an entry written at `timestamp`, to LATEST. Rotation and rewrites replace
+/// LATEST by rename while holding the lock exclusively, so the append holds it shared and
+/// reopens LATEST when `file` was opened before a replacement.
+pub fn append(home: &Path, file: &File, timestamp: u32, line: &str, key: Option<&Key>) -> Result<(), ArchiveError> {
+    rotate(home, Some(timestamp), key)?;
+
+    let _lock = lock(home, libc::LOCK_SH)?;
+    let opened = file.metadata()?;
+    let current = latest_path(home).metadata().ok();
+    let mut file = match current {
+        Some(current) if current.dev() == opened.dev() && current.ino() == opened.ino() => file.try_clone()?,
+        _ => open_latest(home, key)?,
+    };
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+fn write_segment(path: &Path, entries: &[Entry], compression: Compression, key: Option<&Key>) -> Result<(), ArchiveError> {
+    let file = File::create(path)?;
+    let mut writer: Box<dyn Write> = match compression {
+        Compression::None => Box::new(std::io::BufWriter::new(file.try_clone()?)),
+        Compression::Gzip => Box::new(flate2::write::GzEncoder::new(file.try_clone()?, flate2::Compression::best())),
+        Compression::Zstd => Box::new(zstd::Encoder::new(file.try_clone()?, 19)?.auto_finish()),
+    };
+
+    writer.write_all(history::header(key).as_bytes())?;
+    for entry in entries.iter() {
+        writer.write_all(history::encode_line(entry, key).as_bytes())?;
+    }
+    writer.flush()?;
+    drop(writer);
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Merges every segment except the newest `keep` into one, optionally compressed.
+/// Segments are verified against MANIFEST and must decode cleanly before anything is replaced.
+pub fn compact(home: &Path, compression: Compression, keep: usize, key: Option<&Key>) -> Result<CompactReport, ArchiveError> {
+    let _lock = lock(home, libc::LOCK_EX)?;
+    let mut manifest = load_manifest(home)?;
+    if adopt_orphans(home, &mut manifest, key)? {
+        save_manifest(home, &manifest)?;
+    }
+
+    let count = manifest.segments.len().saturating_sub(keep);
+    let candidates: Vec<Segment> = manifest.segments.drain(..count).collect();
+    let unchanged = candidates.len() == 1 && Compression::of(&candidates[0].name) == compression;
+    if candidates.is_empty() || unchanged {
+        return Ok(CompactReport{ merged: vec![], segment: None });
+    }
+
+    let mut entries = vec![];
+    for segment in candidates.iter() {
+        entries.extend(verified_entries(home, segment, key)?);
+    }
+
+    let first = candidates.first().map(|s| s.first).unwrap_or(0);
+    let last = candidates.last().map(|s| s.last).unwrap_or(0);
+    let name = segment_name(first, last, compression);
+    let staging = segments_dir(home).join(format!(".{}.tmp", name));
+    write_segment(&staging, &entries, compression, key)?;
+    std::fs::rename(&staging, segments_dir(home).join(&name))?;
+
+    let segment = describe(home, name, first, last, key)?;
+    manifest.segments.insert(0, segment.clone());
+    save_manifest(home, &manifest)?;
+
+    for old in candidates.iter().filter(|s| s.name != segment.name) {
+        std::fs::remove_file(segments_dir(home).join(&old.name))?;
+    }
+    log::info!("Compacted {} archive segments into '{}'", candidates.len(), segment.name);
+    Ok(CompactReport{ merged: candidates.into_iter().map(|s| s.name).collect(), segment: Some(segment) })
+}
+
+/// Entries of `segment` once it matches its MANIFEST checksum and decodes cleanly
+fn verified_entries(home: &Path, segment: &Segment, key: Option<&Key>) -> Result<Vec<Entry>, ArchiveError> {
+    let path = segments_dir(home).join(&segment.name);
+    if checksum(&path)? != segment.sha256 {
+        return Err(ArchiveError{ cause: format!("Segment '{}' does not match its MANIFEST checksum", segment.name) });
+    }
+    let archive = read_file(&path, key)?;
+    if let Some(malformed) = archive.malformed.first() {
+        return Err(ArchiveError{
+            cause: format!("Segment '{}' has malformed line {}: {}", segment.name, malformed.line, malformed.reason),
+        });
+    }
+    Ok(archive.entries)
+}
+
+/// Renames staged segments whose checksum MANIFEST already lists, finishing a rewrite that was
+/// interrupted between saving MANIFEST and replacing the segment
+fn finish_staged(home: &Path, manifest: &Manifest) -> Result<(), ArchiveError> {
+    for segment in manifest.segments.iter() {
+        let staging = segments_dir(home).join(format!(".{}.tmp", segment.name));
+        if staging.exists() && checksum(&staging)? == segment.sha256 {
+            log::warn!("Finishing the interrupted rewrite of archive segment '{}'", segment.name);
+            std::fs::rename(&staging, segments_dir(home).join(&segment.name))?;
+        }
+    }
+    Ok(())
+}
+
+/// Segments listed in MANIFEST paired with whether their checksum still matches
+pub fn verify(home: &Path) -> Result<Vec<(Segment, bool)>, ArchiveError> {
+    load_manifest(home)?.segments.into_iter().map(|segment| {
+        let path = segments_dir(home).join(&segment.name);
+        let valid = path.exists() && checksum(&path)? == segment.sha256;
+        Ok((segment, valid))
+    }).collect()
+}
+
+/// Rewrites every segment and LATEST with `key`, keeping each file's compression, and
+/// returns how many entries were rewritten. Files are read by their own header and MANIFEST
+/// takes each new checksum before its segment is replaced, so an interrupted run can be
+/// repeated.
+pub fn reencode(home: &Path, key: Option<&Key>) -> Result<usize, ArchiveError> {
+    rewrite(home, key, &mut Some)
+}
+
+/// Passes every archived entry through `edit`, dropping those it returns None for, and writes
+/// the result with `key`. Every file is verified before any is replaced, so corruption is reported
+/// instead of being blessed with a new checksum. Each file is written to a staging file and renamed
+/// over the original, so the old contents are unlinked rather than scrubbed. Segments left without
+/// entries are dropped. Returns how many entries were kept.
+pub fn rewrite(home: &Path, key: Option<&Key>, edit: &mut dyn FnMut(Entry) -> Option<Entry>) -> Result<usize, ArchiveError> {
+    let _lock = lock(home, libc::LOCK_EX)?;
+    let mut manifest = load_manifest(home)?;
+    let adopted = adopt_orphans(home, &mut manifest, key)?;
+    if adopted {
+        save_manifest(home, &manifest)?;
+    }
+    finish_staged(home, &manifest)?;
+
+    let mut files = vec![];
+    for segment in manifest.segments.iter() {
+        files.push((segments_dir(home).join(&segment.name), verified_entries(home, segment, key)?));
+    }
+    let latest = latest_path(home);
+    if latest.exists() {
+        let archive = read_file(&latest, key)?;
+        if let Some(malformed) = archive.malformed.first() {
+            return Err(ArchiveError{ cause: format!("LATEST has malformed line {}: {}", malformed.line, malformed.reason) });
+        }
+        files.push((latest, archive.entries));
+    }
+
+    let mut count = 0;
+    for (path, entries) in files.iter() {
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let entries: Vec<Entry> = entries.iter().cloned().filter_map(&mut *edit).collect();
+        let listed = manifest.segments.iter().position(|s| s.name == name);
+        if let (Some(n), true) = (listed, entries.is_empty()) {
+            manifest.segments.remove(n);
+            save_manifest(home, &manifest)?;
+            std::fs::remove_file(path)?;
+            log::info!("Dropped archive segment '{}' as no entries were kept", name);
+            continue;
+        }
+
+        let staging = path.with_file_name(format!(".{}.tmp", name));
+        write_segment(&staging, &entries, Compression::of(&name), key)?;
+        if let Some(n) = listed {
+            manifest.segments[n].sha256 = checksum(&staging)?;
+            manifest.segments[n].entries = entries.len();
+            save_manifest(home, &manifest)?;
+        }
+        std::fs::rename(&staging, path)?;
+        count += entries.len();
+    }
+    log::info!("Rewrote {} archive files with {} entries", files.len(), count);
+    Ok(count)
+}
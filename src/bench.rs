@@ -0,0 +1,209 @@
+use std::convert::From;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use super::history::Entry;
+use super::init::{self, DataStores, InitError};
+use super::record;
+use super::search::{self, Cursor, Direction, Filter, Mode, SearchError};
+
+/// Queries typed one char at a time, the last one never matches so every keystroke is a worst case
+const QUERIES: &[&str] = &[
+    "git push origin",
+    "kubectl get pods",
+    "docker compose",
+    "grep -rn",
+    "ssh deploy@",
+    "--release",
+    "zebra",
+];
+
+const PROGRAMS: &[&str] = &[
+    "git status", "git commit -m", "git push origin", "git checkout -b", "git log --oneline -n",
+    "cd", "ls -la", "vim", "cargo build", "cargo test", "cargo build --release",
+    "docker ps", "docker run -it", "docker compose up -d", "kubectl get pods -n", "kubectl get svc -n",
+    "ssh deploy@", "grep -rn", "make", "curl -s https://",
+];
+
+const WORDS: &[&str] = &[
+    "api", "auth", "billing", "cache", "config", "core", "data", "deploy", "docs", "events",
+    "feature", "fix", "frontend", "gateway", "infra", "jobs", "logs", "main", "metrics", "migrate",
+    "models", "notify", "orders", "payments", "prod", "queue", "release", "search", "server", "staging",
+    "storage", "sync", "tests", "tools", "users", "utils", "web", "worker",
+];
+
+#[derive(Debug)]
+pub struct BenchError {
+    pub cause: String,
+}
+
+impl From<std::io::Error> for BenchError {
+    fn from(err: std::io::Error) -> Self {
+        BenchError { cause: format!("IO Error encountered: {}", err) }
+    }
+}
+
+impl From<rusqlite::Error> for BenchError {
+    fn from(err: rusqlite::Error) -> Self {
+        BenchError { cause: format!("SQL Error encountered: {}", err) }
+    }
+}
+
+impl From<InitError> for BenchError {
+    fn from(err: InitError) -> Self {
+        BenchError { cause: err.cause }
+    }
+}
+
+impl From<SearchError> for BenchError {
+    fn from(err: SearchError) -> Self {
+        BenchError { cause: err.cause }
+    }
+}
+
+pub struct Options {
+    pub entries: u32,
+    /// reuse a generated history between runs instead of a throwaway temp dir
+    pub dir: Option<PathBuf>,
+}
+
+impl Options {
+    pub fn parse(flags: &[String]) -> Result<Self, BenchError> {
+        let mut options = Options{ entries: 1_000_000, dir: None };
+        let mut args = flags.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(BenchError{ cause: format!("Missing value for '{}'", arg) });
+            match arg.as_str() {
+                "--entries" => {
+                    let entries = value()?;
+                    options.entries = entries.parse().map_err(|_| BenchError{ cause: format!("Invalid entry count '{}'", entries) })?;
+                }
+                "--dir" => options.dir = Some(PathBuf::from(value()?)),
+                _ => return Err(BenchError{ cause: format!("Unknown bench flag '{}'", arg) }),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// xorshift64, the history only needs to be varied and the same on every run
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.next() as usize % items.len()]
+    }
+}
+
+fn synthetic_command(rng: &mut Rng) -> String {
+    let program = rng.pick(PROGRAMS);
+    match rng.next() % 3 {
+        0 => program.to_owned(),
+        1 => format!("{} {}", program, rng.pick(WORDS)),
+        _ => format!("{} {}/{}-{}", program, rng.pick(WORDS), rng.pick(WORDS), rng.next() % 1000),
+    }
+}
+
+fn generate(deps: &DataStores, entries: u32) -> Result<(), BenchError> {
+    let now = record::now().map_err(|e| BenchError{ cause: e.cause })?;
+    let mut rng = Rng(0x5c21_be5e_ed00_0001);
+
+    let mut index = deps.index.try_lock().unwrap();
+    let tx = index.transaction()?;
+    for n in 0..entries {
+        let mut entry = Entry::new(synthetic_command(&mut rng), now.saturating_sub(entries - n));
+        entry.exit_code = Some(rng.next().is_multiple_of(8) as i32);
+        entry.cwd = Some(format!("/home/dev/{}", rng.pick(WORDS)));
+        record::index_entry(&tx, &entry)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    sorted[(sorted.len() - 1) * p / 100]
+}
+
+/// Times `find_next_match` after every keystroke of every query, plus a few Ctrl-R steps back
+fn measure(deps: &DataStores, mode: Mode) -> Result<Vec<Duration>, BenchError> {
+    let mut timings = vec![];
+    for query in QUERIES {
+        let mut typed = String::new();
+        let mut cursor = Cursor{ direction: Direction::Older, navigated: false, oid: u32::MAX, rank: 0 };
+        for c in query.chars() {
+            typed.push(c);
+            let start = Instant::now();
+            let (_, next) = search::find_next_match(deps.clone(), typed.clone(), mode, &Filter::default(), cursor)?;
+            timings.push(start.elapsed());
+            cursor = next;
+        }
+        for _ in 0..5 {
+            cursor.navigated = true;
+            cursor.oid = cursor.oid.saturating_sub(1);
+            cursor.rank += 1;
+            let start = Instant::now();
+            let (_, next) = search::find_next_match(deps.clone(), typed.clone(), mode, &Filter::default(), cursor)?;
+            timings.push(start.elapsed());
+            cursor = next;
+        }
+    }
+    timings.sort();
+    Ok(timings)
+}
+
+/// A generated scribe dir, removed on drop so a failed run leaves nothing behind
+struct Scratch(PathBuf);
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.0) {
+            log::warn!("Unable to remove the bench dir {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+pub fn bench(options: Options) -> Result<(), BenchError> {
+    let home = options.dir.clone().unwrap_or_else(|| std::env::temp_dir().join(format!("scribe-bench-{}", std::process::id())));
+    let _scratch = if options.dir.is_none() { Some(Scratch(home.clone())) } else { None };
+    for dir in init::dirs() {
+        std::fs::create_dir_all(home.join(dir))?;
+    }
+
+    let mut deps = init::deps(home.clone())?;
+    let existing: u32 = deps.index.try_lock().unwrap().query_row("SELECT count(*) FROM history", [], |row| row.get(0))?;
+    if existing < options.entries {
+        println!("Generating {} synthetic commands in {}", options.entries - existing, home.display());
+        let start = Instant::now();
+        generate(&deps, options.entries - existing)?;
+        println!("Generated in {:.1}s", start.elapsed().as_secs_f64());
+    }
+
+    let fts = deps.fts;
+    let mut runs = vec![];
+    for mode in [Mode::Substring, Mode::Prefix, Mode::Regex, Mode::Fuzzy] {
+        if fts && (mode == Mode::Substring || mode == Mode::Prefix) {
+            runs.push((mode, true));
+        }
+        runs.push((mode, false));
+    }
+
+    println!("{:<10} {:<6} {:>10} {:>10} {:>10} {:>10}", "mode", "index", "keystrokes", "p50 ms", "p95 ms", "max ms");
+    for (mode, indexed) in runs {
+        deps.fts = indexed;
+        let timings = measure(&deps, mode)?;
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        println!(
+            "{:<10} {:<6} {:>10} {:>10.2} {:>10.2} {:>10.2}",
+            mode.name(), if indexed { "fts5" } else { "like" }, timings.len(),
+            ms(percentile(&timings, 50)), ms(percentile(&timings, 95)), ms(percentile(&timings, 100)),
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,340 @@
+// Settings read from `config.toml` in the scribe dir. Missing settings take their defaults,
+// unknown ones and invalid values are errors naming the line they are on. Each setting can
+// be overridden with an environment variable named after its dotted key, `search.limit` is
+// SCRIBE_SEARCH_LIMIT. The documented default file lives in `etc/config.toml`.
+
+use std::convert::From;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use termion::color;
+
+use super::search::{Dedup, Mode, Rank, Scope};
+
+pub const DEFAULT: &str = include_str!("etc/config.toml");
+
+pub struct ConfigError {
+    pub cause: String,
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(err: std::io::Error) -> Self {
+        ConfigError{ cause: format!("IO Error: {}", err) }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub log_level: Level,
+    pub search: Search,
+    pub colors: Colors,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Search {
+    pub mode: Mode,
+    pub rank: Rank,
+    pub scope: Scope,
+    pub dedup: Dedup,
+    pub list: bool,
+    pub fullscreen: bool,
+    pub limit: NonZeroUsize,
+    pub prompt_prefix: String,
+    pub search_prefix: String,
+    pub max_width: NonZeroUsize,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Colors {
+    pub enabled: bool,
+    pub prompt: Color,
+    pub error: Color,
+    pub highlight: Color,
+    pub muted: Color,
+    pub directory: Color,
+    pub success: Color,
+    pub selection: Color,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    LightBlack,
+    LightRed,
+    LightGreen,
+    LightYellow,
+    LightBlue,
+    LightMagenta,
+    LightCyan,
+    LightWhite,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config{ log_level: Level::Info, search: Search::default(), colors: Colors::default() }
+    }
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Search{
+            mode: Mode::Substring,
+            rank: Rank::Recency,
+            scope: Scope::Global,
+            dedup: Dedup::Global,
+            list: false,
+            fullscreen: false,
+            limit: NonZeroUsize::new(20).unwrap(),
+            prompt_prefix: "scribe".to_owned(),
+            search_prefix: "~ ".to_owned(),
+            max_width: NonZeroUsize::new(500).unwrap(),
+        }
+    }
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Colors{
+            enabled: true,
+            prompt: Color::Green,
+            error: Color::Red,
+            highlight: Color::Yellow,
+            muted: Color::LightBlack,
+            directory: Color::Blue,
+            success: Color::Green,
+            selection: Color::LightBlack,
+        }
+    }
+}
+
+impl Level {
+    pub fn filter(self) -> log::LevelFilter {
+        match self {
+            Level::Off => log::LevelFilter::Off,
+            Level::Error => log::LevelFilter::Error,
+            Level::Warn => log::LevelFilter::Warn,
+            Level::Info => log::LevelFilter::Info,
+            Level::Debug => log::LevelFilter::Debug,
+            Level::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+impl Color {
+    pub fn termion(self) -> &'static dyn color::Color {
+        match self {
+            Color::Black => &color::Black,
+            Color::Red => &color::Red,
+            Color::Green => &color::Green,
+            Color::Yellow => &color::Yellow,
+            Color::Blue => &color::Blue,
+            Color::Magenta => &color::Magenta,
+            Color::Cyan => &color::Cyan,
+            Color::White => &color::White,
+            Color::LightBlack => &color::LightBlack,
+            Color::LightRed => &color::LightRed,
+            Color::LightGreen => &color::LightGreen,
+            Color::LightYellow => &color::LightYellow,
+            Color::LightBlue => &color::LightBlue,
+            Color::LightMagenta => &color::LightMagenta,
+            Color::LightCyan => &color::LightCyan,
+            Color::LightWhite => &color::LightWhite,
+        }
+    }
+}
+
+pub fn path(home: &Path) -> PathBuf {
+    home.join("config.toml")
+}
+
+/// Environment variable overriding `key`
+pub fn env_var(key: &str) -> String {
+    format!("SCRIBE_{}", key.replace('.', "_").to_uppercase())
+}
+
+fn tree(config: &Config) -> toml::Value {
+    toml::Value::try_from(config).expect("the config is representable as TOML")
+}
+
+/// Dotted names of every setting such as `search.limit`
+pub fn keys() -> Vec<String> {
+    fn walk(prefix: &str, value: &toml::Value, keys: &mut Vec<String>) {
+        match value {
+            toml::Value::Table(table) => {
+                for (name, value) in table.iter() {
+                    let key = if prefix.is_empty() { name.clone() } else { format!("{}.{}", prefix, name) };
+                    walk(&key, value, keys);
+                }
+            }
+            _ => keys.push(prefix.to_owned()),
+        }
+    }
+    let mut keys = vec![];
+    walk("", &tree(&Config::default()), &mut keys);
+    keys
+}
+
+fn unknown(key: &str) -> ConfigError {
+    ConfigError{ cause: format!("Unknown setting '{}', run 'scribe config list' to see them all", key) }
+}
+
+/// A value given on the command line or in the environment, anything that is not a TOML value is a string
+fn raw_value(raw: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", raw)).ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_owned()))
+}
+
+/// Reads `text`, errors are prefixed with `origin` and the line they were found on
+fn parse(text: &str, origin: &str) -> Result<Config, ConfigError> {
+    toml::from_str(text).map_err(|e: toml::de::Error| {
+        let line = e.span().map(|span| format!(":{}", text[..span.start].matches('\n').count() + 1)).unwrap_or_default();
+        ConfigError{ cause: format!("{}{}: {}", origin, line, e.message().trim()) }
+    })
+}
+
+/// The settings in `home`, with the environment overrides applied
+pub fn load(home: &Path) -> Result<Config, ConfigError> {
+    let path = path(home);
+    let mut config = if path.exists() {
+        parse(&std::fs::read_to_string(&path)?, &path.display().to_string())?
+    } else {
+        Config::default()
+    };
+
+    for key in keys() {
+        let var = env_var(&key);
+        if let Ok(raw) = std::env::var(&var) {
+            let mut tree = tree(&config);
+            let mut slot = &mut tree;
+            for part in key.split('.') {
+                slot = slot.get_mut(part).ok_or_else(|| unknown(&key))?;
+            }
+            *slot = raw_value(&raw);
+            config = tree.try_into().map_err(|e: toml::de::Error| ConfigError{
+                cause: format!("Invalid value '{}' in {}: {}", raw, var, e.message().trim()),
+            })?;
+        }
+    }
+    Ok(config)
+}
+
+/// The value of `key` as TOML
+pub fn lookup(config: &Config, key: &str) -> Result<toml::Value, ConfigError> {
+    let mut value = &tree(config);
+    for part in key.split('.') {
+        value = value.get(part).ok_or_else(|| unknown(key))?;
+    }
+    match value {
+        toml::Value::Table(_) => Err(unknown(key)),
+        value => Ok(value.clone()),
+    }
+}
+
+/// The value of `key` for scripts, strings without quotes
+pub fn get(config: &Config, key: &str) -> Result<String, ConfigError> {
+    match lookup(config, key)? {
+        toml::Value::String(text) => Ok(text),
+        value => Ok(value.to_string()),
+    }
+}
+
+/// Writes the documented default file unless one exists, returning whether it was written
+pub fn write_default(home: &Path) -> Result<bool, ConfigError> {
+    match OpenOptions::new().write(true).create_new(true).open(path(home)) {
+        Ok(mut file) => {
+            file.write_all(DEFAULT.as_bytes())?;
+            Ok(true)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Changes one setting in the file, keeping its comments and layout. The new value is validated
+/// first and the file is replaced by rename, so a rejected value leaves it untouched.
+pub fn set(home: &Path, key: &str, raw: &str) -> Result<(), ConfigError> {
+    if !keys().iter().any(|k| k == key) {
+        return Err(unknown(key));
+    }
+    write_default(home)?;
+    let path = path(home);
+    let mut document: toml_edit::DocumentMut = std::fs::read_to_string(&path)?.parse().map_err(|e: toml_edit::TomlError| {
+        ConfigError{ cause: format!("{}: {}", path.display(), e.message().trim()) }
+    })?;
+
+    let value = raw.parse::<toml_edit::Value>().unwrap_or_else(|_| raw.into());
+    let (section, name) = match key.split_once('.') {
+        Some((section, name)) => (Some(section), name),
+        None => (None, key),
+    };
+    let table = match section {
+        Some(section) => document.entry(section).or_insert(toml_edit::table()).as_table_like_mut()
+            .ok_or(ConfigError{ cause: format!("'{}' in {} is not a table", section, path.display()) })?,
+        None => document.as_table_mut(),
+    };
+    match table.get_mut(name).and_then(|item| item.as_value_mut()) {
+        // keeps the comment trailing the old value
+        Some(old) => {
+            let decor = old.decor().clone();
+            *old = value;
+            *old.decor_mut() = decor;
+        }
+        None => {
+            table.insert(name, toml_edit::Item::Value(value));
+        }
+    }
+
+    let text = document.to_string();
+    toml::from_str::<Config>(&text).map_err(|e| ConfigError{
+        cause: format!("Invalid value '{}' for {}: {}", raw, key, e.message().trim()),
+    })?;
+    let staging = path.with_file_name(".config.toml.tmp");
+    std::fs::write(&staging, text)?;
+    std::fs::rename(&staging, &path)?;
+    Ok(())
+}
+
+/// Opens the file in $VISUAL or $EDITOR, then checks what was saved
+pub fn edit(home: &Path) -> Result<(), ConfigError> {
+    write_default(home)?;
+    let path = path(home);
+    let editor = std::env::var("VISUAL").or_else(|_| std::env::var("EDITOR")).unwrap_or_else(|_| "vi".to_owned());
+    // run through the shell so editors configured with arguments such as `code --wait` work
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(&path)
+        .status()?;
+    if !status.success() {
+        return Err(ConfigError{ cause: format!("Editor '{}' exited with {}", editor, status) });
+    }
+    parse(&std::fs::read_to_string(&path)?, &path.display().to_string())?;
+    Ok(())
+}
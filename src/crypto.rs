@@ -0,0 +1,352 @@
+// Encryption at rest. One 256-bit key seals every archive line with XChaCha20-Poly1305 and
+// keys the SQLCipher index. It is kept in `key` under the scribe dir, either stored as is
+// after being generated from the OS random number generator, or derived with Argon2id from
+// a passphrase. Passphrase keys are never written to the scribe dir, only the salt, the KDF
+// cost and a sealed check value so a wrong passphrase is reported as such instead of every
+// line looking corrupted.
+//
+//   version=1,kdf=none        version=1,kdf=argon2id,m=65536,t=3,p=1
+//   key=base64(key)           salt=base64(salt)
+//                             check=seal("scribe")
+//
+// Deriving a passphrase key takes seconds, far too long to repeat for every recorded command,
+// so `scribe unlock` derives it once and caches it for the login session in a file only the
+// user can read under $XDG_RUNTIME_DIR, which is cleared on logout. Without a runtime dir the
+// cache goes in a private directory under the temp dir instead. `scribe lock` removes it.
+//
+// Encryption is opt-in through `scribe encrypt` rather than set up on first run. A generated
+// key has to live next to the history it protects, and a passphrase cannot be asked for from
+// the shell hook that usually creates the scribe dir, so enabling it is left to the user.
+
+use std::convert::{From, TryInto};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use termion::input::TermRead;
+
+pub const PASSPHRASE_VAR: &str = "SCRIBE_PASSPHRASE";
+const KEY_VERSION: u32 = 1;
+/// Argon2id cost for new passphrase keys, 64 MiB of memory over 3 passes in one lane
+const ARGON2_COST: (u32, u32, u32) = (64 * 1024, 3, 1);
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 24;
+const CHECK: &[u8] = b"scribe";
+
+pub struct CryptoError {
+    pub cause: String,
+}
+
+impl From<std::io::Error> for CryptoError {
+    fn from(err: std::io::Error) -> Self {
+        CryptoError{ cause: format!("IO Error: {}", err) }
+    }
+}
+
+#[derive(Clone)]
+pub struct Key([u8; 32]);
+
+pub fn key_path(home: &Path) -> PathBuf {
+    home.join("key")
+}
+
+impl Key {
+    /// Encrypts `plaintext` under a fresh random nonce, returning base64(nonce || ciphertext)
+    pub fn seal(&self, plaintext: &[u8]) -> String {
+        let cipher = XChaCha20Poly1305::new((&self.0).into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut sealed = nonce.to_vec();
+        sealed.extend(cipher.encrypt(&nonce, plaintext).expect("XChaCha20-Poly1305 only rejects messages over 256 GiB"));
+        base64::encode(&sealed)
+    }
+
+    pub fn open(&self, sealed: &str) -> Result<Vec<u8>, String> {
+        let bytes = base64::decode(sealed).map_err(|e| format!("sealed line is not valid base64: {}", e))?;
+        if bytes.len() < NONCE_SIZE {
+            return Err("sealed line is too short".to_owned());
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_SIZE);
+        XChaCha20Poly1305::new((&self.0).into())
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "unable to decrypt, the key is wrong or the line was modified".to_owned())
+    }
+
+    /// Raw key syntax for SQLCipher's `PRAGMA key`, which uses it directly instead of running its own KDF
+    fn sqlcipher(&self) -> String {
+        format!("x'{}'", self.0.iter().map(|b| format!("{:02X}", b)).collect::<String>())
+    }
+}
+
+fn derive(passphrase: &str, salt: &[u8], (memory, passes, lanes): (u32, u32, u32)) -> Result<Key, CryptoError> {
+    let params = argon2::Params::new(memory, passes, lanes, Some(32))
+        .map_err(|e| CryptoError{ cause: format!("Invalid Argon2 parameters: {}", e) })?;
+    let mut key = [0u8; 32];
+    argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| CryptoError{ cause: format!("Unable to derive the key: {}", e) })?;
+    Ok(Key(key))
+}
+
+fn decode_bytes(value: Option<&String>, name: &str) -> Result<Vec<u8>, CryptoError> {
+    let value = value.ok_or(CryptoError{ cause: format!("Key file is missing '{}'", name) })?;
+    base64::decode(value).map_err(|e| CryptoError{ cause: format!("Key file '{}' is not valid base64: {}", name, e) })
+}
+
+fn raw_key(bytes: Vec<u8>) -> Result<Key, CryptoError> {
+    let key: [u8; 32] = bytes.try_into().map_err(|_| CryptoError{ cause: "Key file holds a key of the wrong size".to_owned() })?;
+    Ok(Key(key))
+}
+
+/// How the key of an encrypted scribe dir is obtained
+enum Source {
+    Stored(Key),
+    Passphrase(Passphrase),
+}
+
+/// Fields of a passphrase key file, everything needed to derive and verify the key
+struct Passphrase {
+    salt: Vec<u8>,
+    cost: (u32, u32, u32),
+    check: String,
+}
+
+impl Passphrase {
+    fn unlocks(&self, key: &Key) -> bool {
+        key.open(&self.check).ok().as_deref() == Some(CHECK)
+    }
+
+    fn derive(&self, passphrase: &str) -> Result<Key, CryptoError> {
+        let key = derive(passphrase, &self.salt, self.cost)?;
+        if !self.unlocks(&key) {
+            return Err(CryptoError{ cause: "The passphrase does not unlock the history".to_owned() });
+        }
+        Ok(key)
+    }
+
+    /// Keys are cached per salt, so scribe dirs with different passphrases do not collide
+    fn session_path(&self) -> Result<PathBuf, CryptoError> {
+        let salt: String = self.salt.iter().map(|b| format!("{:02x}", b)).collect();
+        Ok(session_dir()?.join(format!("{}.key", salt)))
+    }
+
+    /// Present once recording was told the history is locked in this session
+    fn notice_path(&self) -> Result<PathBuf, CryptoError> {
+        Ok(self.session_path()?.with_extension("locked"))
+    }
+
+    fn cached(&self) -> Option<Key> {
+        let path = self.session_path().ok()?;
+        let text = std::fs::read_to_string(path).ok()?;
+        let key = raw_key(base64::decode(text.trim()).ok()?).ok()?;
+        Some(key).filter(|key| self.unlocks(key))
+    }
+
+    fn cache(&self, key: &Key) -> Result<(), CryptoError> {
+        let path = self.session_path()?;
+        let staging = path.with_extension("tmp");
+        let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&staging)?;
+        file.write_all(base64::encode(&key.0).as_bytes())?;
+        std::fs::rename(&staging, &path)?;
+        Ok(())
+    }
+}
+
+/// Private directory for session keys, created readable only by the user. A directory that
+/// already exists is only used when the user owns it and nobody else can read it.
+fn session_dir() -> Result<PathBuf, CryptoError> {
+    let uid = unsafe { libc::getuid() };
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime) => PathBuf::from(runtime).join("scribe"),
+        None => std::env::temp_dir().join(format!("scribe-{}", uid)),
+    };
+    if let Err(e) = std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        if e.kind() != std::io::ErrorKind::AlreadyExists {
+            return Err(e.into());
+        }
+    }
+    let metadata = std::fs::symlink_metadata(&dir)?;
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        return Err(CryptoError{ cause: format!("Refusing to keep the session key in {}, it must be a directory only you can access", dir.display()) });
+    }
+    Ok(dir)
+}
+
+fn read_source(home: &Path) -> Result<Option<Source>, CryptoError> {
+    let path = key_path(home);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let mut fields = std::collections::HashMap::new();
+    for line in BufReader::new(std::fs::File::open(&path)?).lines() {
+        for pair in line?.split(',') {
+            if let Some((name, value)) = pair.split_once('=') {
+                fields.insert(name.to_owned(), value.to_owned());
+            }
+        }
+    }
+    if fields.get("version").map(String::as_str) != Some(&KEY_VERSION.to_string()) {
+        return Err(CryptoError{ cause: format!("Unsupported key file version in {}", path.display()) });
+    }
+
+    match fields.get("kdf").map(String::as_str) {
+        Some("none") => Ok(Some(Source::Stored(raw_key(decode_bytes(fields.get("key"), "key")?)?))),
+        Some("argon2id") => {
+            let cost = |name: &str| fields.get(name).and_then(|v| v.parse().ok())
+                .ok_or(CryptoError{ cause: format!("Key file has an invalid Argon2 '{}'", name) });
+            Ok(Some(Source::Passphrase(Passphrase{
+                salt: decode_bytes(fields.get("salt"), "salt")?,
+                cost: (cost("m")?, cost("t")?, cost("p")?),
+                check: fields.get("check").cloned().ok_or(CryptoError{ cause: "Key file is missing 'check'".to_owned() })?,
+            })))
+        }
+        kdf => Err(CryptoError{ cause: format!("Unsupported key derivation {:?} in {}", kdf, path.display()) }),
+    }
+}
+
+/// The key the scribe dir at `home` is encrypted with, or None when it is stored in plain text.
+/// A passphrase key comes from the session cache, or from SCRIBE_PASSPHRASE for scripts.
+pub fn load(home: &Path) -> Result<Option<Key>, CryptoError> {
+    let passphrase = match read_source(home)? {
+        None => return Ok(None),
+        Some(Source::Stored(key)) => return Ok(Some(key)),
+        Some(Source::Passphrase(passphrase)) => passphrase,
+    };
+    if let Some(key) = passphrase.cached() {
+        return Ok(Some(key));
+    }
+
+    let given = std::env::var(PASSPHRASE_VAR).map_err(|_| CryptoError{
+        cause: "The history is locked, run 'scribe unlock' to unlock it for this session".to_owned(),
+    })?;
+    let key = passphrase.derive(&given).map_err(|e| CryptoError{ cause: format!("{} in {}", e.cause, PASSPHRASE_VAR) })?;
+    if let Err(e) = passphrase.cache(&key) {
+        log::warn!("Unable to cache the session key: {}", e.cause);
+    }
+    Ok(Some(key))
+}
+
+/// Whether the history is protected by a passphrase that neither the session cache nor
+/// SCRIBE_PASSPHRASE can provide, so `load` would fail
+pub fn locked(home: &Path) -> Result<bool, CryptoError> {
+    match read_source(home)? {
+        Some(Source::Passphrase(passphrase)) => Ok(passphrase.cached().is_none() && std::env::var_os(PASSPHRASE_VAR).is_none()),
+        _ => Ok(false),
+    }
+}
+
+/// Returns true the first time it is called for a locked history in this session, so the
+/// shell hooks can say so once instead of before every prompt
+pub fn first_locked_notice(home: &Path) -> Result<bool, CryptoError> {
+    let passphrase = match read_source(home)? {
+        Some(Source::Passphrase(passphrase)) => passphrase,
+        _ => return Ok(false),
+    };
+    match OpenOptions::new().write(true).create_new(true).mode(0o600).open(passphrase.notice_path()?) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Asks for the passphrase and caches the key until logout, returns false when the history
+/// is not protected by a passphrase
+pub fn unlock_session(home: &Path) -> Result<bool, CryptoError> {
+    let passphrase = match read_source(home)? {
+        Some(Source::Passphrase(passphrase)) => passphrase,
+        _ => return Ok(false),
+    };
+    let key = passphrase.derive(&read_passphrase(false)?)?;
+    passphrase.cache(&key)?;
+    // locking again later is reported again
+    match std::fs::remove_file(passphrase.notice_path()?) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    Ok(true)
+}
+
+/// Removes the cached session key, returns whether there was one
+pub fn lock_session(home: &Path) -> Result<bool, CryptoError> {
+    match read_source(home)? {
+        Some(Source::Passphrase(passphrase)) => match std::fs::remove_file(passphrase.session_path()?) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        },
+        _ => Ok(false),
+    }
+}
+
+/// Generates a key, or derives one from `passphrase`, and writes the key file readable only by
+/// its owner. A passphrase key is also cached for the session.
+pub fn create(home: &Path, passphrase: Option<&str>) -> Result<Key, CryptoError> {
+    let (key, contents, session) = match passphrase {
+        None => {
+            let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+            (Key(key.into()), format!("version={},kdf=none\nkey={}\n", KEY_VERSION, base64::encode(&key)), None)
+        }
+        Some(passphrase) => {
+            let mut salt = [0u8; SALT_SIZE];
+            OsRng.fill_bytes(&mut salt);
+            let key = derive(passphrase, &salt, ARGON2_COST)?;
+            let (m, t, p) = ARGON2_COST;
+            let session = Passphrase{ salt: salt.to_vec(), cost: ARGON2_COST, check: key.seal(CHECK) };
+            let contents = format!(
+                "version={},kdf=argon2id,m={},t={},p={}\nsalt={}\ncheck={}\n",
+                KEY_VERSION, m, t, p, base64::encode(&salt), session.check,
+            );
+            (key, contents, Some(session))
+        }
+    };
+
+    let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(key_path(home))?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    if let Some(session) = session {
+        session.cache(&key)?;
+    }
+    Ok(key)
+}
+
+/// Reads the passphrase from SCRIBE_PASSPHRASE, or asks for it on the terminal, twice with `confirm`
+pub fn read_passphrase(confirm: bool) -> Result<String, CryptoError> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_VAR) {
+        return Ok(passphrase);
+    }
+
+    let mut tty = termion::get_tty()?;
+    let mut ask = |prompt: &str| -> Result<String, CryptoError> {
+        write!(tty, "{}", prompt)?;
+        tty.flush()?;
+        let passphrase = tty.try_clone()?.read_passwd(&mut tty)?.unwrap_or_default();
+        writeln!(tty)?;
+        Ok(passphrase)
+    };
+    let passphrase = ask("Passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err(CryptoError{ cause: "The passphrase must not be empty".to_owned() });
+    }
+    if confirm && ask("Repeat passphrase: ")? != passphrase {
+        return Err(CryptoError{ cause: "The passphrases do not match".to_owned() });
+    }
+    Ok(passphrase)
+}
+
+/// Keys a freshly opened index and checks it can be read, a plain index opened with a key
+/// or an encrypted one opened with the wrong key both fail here
+pub fn unlock(index: &rusqlite::Connection, key: Option<&Key>) -> Result<(), CryptoError> {
+    let key = match key {
+        Some(key) => key,
+        None => return Ok(()),
+    };
+    index.execute_batch(&format!("PRAGMA key = \"{}\";", key.sqlcipher()))
+        .and_then(|_| index.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(())))
+        .map_err(|e| CryptoError{
+            cause: format!("Unable to unlock the index with the key, run 'scribe encrypt' to finish encrypting it ({})", e),
+        })
+}
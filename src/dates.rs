@@ -0,0 +1,136 @@
+// Date handling for filters and display, backed by libc so local time follows TZ
+
+/// Returns (year, month, day) in UTC for a unix timestamp
+pub fn civil(timestamp: u32) -> (i64, u32, u32) {
+    // civil_from_days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = timestamp as i64 / 86400 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn local_tm(timestamp: u32) -> libc::tm {
+    let time = timestamp as libc::time_t;
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&time, &mut tm);
+        tm
+    }
+}
+
+fn local_timestamp(year: i64, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> Result<u32, String> {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = (year - 1900) as libc::c_int;
+    tm.tm_mon = month as libc::c_int - 1;
+    tm.tm_mday = day as libc::c_int;
+    tm.tm_hour = hour as libc::c_int;
+    tm.tm_min = minute as libc::c_int;
+    tm.tm_sec = second as libc::c_int;
+    tm.tm_isdst = -1;
+
+    let time = unsafe { libc::mktime(&mut tm) };
+    if time < 0 {
+        return Err(format!("{:04}-{:02}-{:02} is out of range", year, month, day));
+    }
+    Ok(time as u32)
+}
+
+fn start_of_day(timestamp: u32) -> Result<u32, String> {
+    let tm = local_tm(timestamp);
+    local_timestamp(tm.tm_year as i64 + 1900, tm.tm_mon as u32 + 1, tm.tm_mday as u32, 0, 0, 0)
+}
+
+fn number(text: &str, what: &str, range: std::ops::RangeInclusive<u32>) -> Result<u32, String> {
+    text.parse::<u32>().ok()
+        .filter(|n| range.contains(n))
+        .ok_or(format!("invalid {} '{}'", what, text))
+}
+
+/// Parses `YYYY-MM-DD` with an optional `THH:MM[:SS]` or ` HH:MM[:SS]` time in local time
+fn parse_datetime(input: &str) -> Result<u32, String> {
+    let (date, time) = match input.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (input, None),
+    };
+
+    let parts: Vec<&str> = date.split('-').collect();
+    if parts.len() != 3 || parts[0].len() != 4 {
+        return Err(format!("expected YYYY-MM-DD but found '{}'", date));
+    }
+    let year = number(parts[0], "year", 1970..=2105)? as i64;
+    let month = number(parts[1], "month", 1..=12)?;
+    let day = number(parts[2], "day", 1..=31)?;
+
+    let (hour, minute, second) = match time {
+        Some(time) => {
+            let parts: Vec<&str> = time.split(':').collect();
+            if parts.len() < 2 || parts.len() > 3 {
+                return Err(format!("expected HH:MM[:SS] but found '{}'", time));
+            }
+            let second = parts.get(2).map(|s| number(s, "second", 0..=59)).transpose()?.unwrap_or(0);
+            (number(parts[0], "hour", 0..=23)?, number(parts[1], "minute", 0..=59)?, second)
+        }
+        None => (0, 0, 0),
+    };
+    local_timestamp(year, month, day, hour, minute, second)
+}
+
+/// Parses a duration like `90s`, `15m`, `2h`, `3d` or `1w` into seconds
+pub fn parse_duration(input: &str) -> Result<u32, String> {
+    let split = input.find(|c: char| !c.is_ascii_digit()).unwrap_or(input.len());
+    let (digits, unit) = input.split_at(split);
+    let amount: u32 = digits.parse().map_err(|_| format!("invalid duration '{}'", input))?;
+    let scale = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("invalid duration unit '{}' in '{}', expected s, m, h, d or w", unit, input)),
+    };
+    amount.checked_mul(scale).ok_or(format!("duration '{}' is too large", input))
+}
+
+/// Formats a timestamp as `YYYY-MM-DD HH:MM:SS` in local time
+pub fn format_local(timestamp: u32) -> String {
+    let tm = local_tm(timestamp);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec,
+    )
+}
+
+/// Short age such as `45s`, `3h` or `2w`, using the same units as `parse_duration`
+pub fn ago(timestamp: u32, now: u32) -> String {
+    let elapsed = now.saturating_sub(timestamp);
+    let (amount, unit) = match elapsed {
+        0..=59 => (elapsed, "s"),
+        60..=3_599 => (elapsed / 60, "m"),
+        3_600..=86_399 => (elapsed / 3_600, "h"),
+        86_400..=604_799 => (elapsed / 86_400, "d"),
+        _ => (elapsed / 604_800, "w"),
+    };
+    format!("{}{}", amount, unit)
+}
+
+/// Parses a point in time for filters: a unix timestamp, `YYYY-MM-DD[ HH:MM[:SS]]`,
+/// `now`, `today`, `yesterday`, or a duration ago such as `3d`
+pub fn parse(input: &str, now: u32) -> Result<u32, String> {
+    match input {
+        "now" => Ok(now),
+        "today" => start_of_day(now),
+        // step back half a day so DST changes cannot skip over yesterday
+        "yesterday" => start_of_day(now).map(|today| today.saturating_sub(12 * 60 * 60)).and_then(start_of_day),
+        _ if input.len() >= 9 && input.bytes().all(|b| b.is_ascii_digit()) => {
+            input.parse().map_err(|_| format!("invalid timestamp '{}'", input))
+        }
+        _ if input.contains('-') => parse_datetime(input),
+        _ => parse_duration(input).map(|ago| now.saturating_sub(ago)),
+    }
+}
@@ -25,7 +25,7 @@ impl log::Log for Logger {
      }
 }
 
-pub fn init(home: std::path::PathBuf) -> Result<(), SetLoggerError> {
+pub fn init(home: std::path::PathBuf, level: log::LevelFilter) -> Result<(), SetLoggerError> {
     let file = OpenOptions::new()
         .create(true)
         .append(true)
@@ -33,6 +33,6 @@ pub fn init(home: std::path::PathBuf) -> Result<(), SetLoggerError> {
         .open(home.join("log").join("debug.log"))
         .unwrap();
 
-    log::set_max_level(log::LevelFilter::Info);
+    log::set_max_level(level);
     log::set_boxed_logger(Box::new(Logger{stream: Mutex::new(file)}))
 }
\ No newline at end of file
@@ -0,0 +1,81 @@
+# Copyright (C) Brandon Waite 2020  - All Rights Reserved
+# Unauthorized copying of this file, via any medium, is strictly prohibited
+# Proprietary
+# Updated by Brandon Waite, May 28 2020
+
+_SCRIBE_SESSION=$( scribe session )
+_SCRIBE_READY=""
+_SCRIBE_PREV_DEBUG_TRAP=$( trap -p DEBUG )
+_SCRIBE_PREV_HISTORY_SEARCH=$( bind -p | grep -F '"\C-r"' )
+_SCRIBE_HISTCMD=$( HISTTIMEFORMAT= builtin history 1 | awk '{ print $1 }' )
+
+# Runs from the DEBUG trap, which fires before every simple command. Only the first
+# one after the prompt is the command line the user typed.
+_scribe-recorder() {
+    if [[ -z "$_SCRIBE_READY" || -n "${READLINE_POINT+x}" || -n "$COMP_LINE" ]]; then
+        return
+    fi
+    _SCRIBE_READY=""
+
+    local entry number
+    entry=$( HISTTIMEFORMAT= builtin history 1 )
+    number=$( awk '{ print $1 }' <<< "$entry" )
+    # an unchanged history number means bash chose not to save the line (HISTCONTROL, HISTIGNORE)
+    if [[ "$number" == "$_SCRIBE_HISTCMD" ]]; then
+        return
+    fi
+    _SCRIBE_HISTCMD="$number"
+
+    _SCRIBE_CMD=$( sed -e '1s/^ *[0-9]*[* ] //' <<< "$entry" )
+    _SCRIBE_CWD="$PWD"
+    printf -v _SCRIBE_START '%(%s)T' -1
+}
+_scribe-finisher() {
+    local code=$?
+    if [[ -z "$_SCRIBE_START" ]]; then
+        return
+    fi
+
+    local end cmd
+    printf -v end '%(%s)T' -1
+    cmd=$( scribe record --exit "$code" --start "$_SCRIBE_START" --end "$end" \
+        --cwd "$_SCRIBE_CWD" --session "$_SCRIBE_SESSION" -- "$_SCRIBE_CMD" )
+    unset _SCRIBE_CMD _SCRIBE_CWD _SCRIBE_START
+    if [[ "$cmd" == "release" || "$cmd" == "release-hooks" ]]; then
+        _scribe-release
+    fi
+}
+_scribe-ready() {
+    _SCRIBE_READY=1
+}
+PROMPT_COMMAND="_scribe-finisher${PROMPT_COMMAND:+; $PROMPT_COMMAND}; _scribe-ready"
+trap '_scribe-recorder' DEBUG
+_scribe-history() {
+    READLINE_LINE=$(scribe search --interactive --session "$_SCRIBE_SESSION")
+    READLINE_POINT=${#READLINE_LINE}
+}
+bind -x '"\C-r": _scribe-history'
+_scribe-release() {
+    local args="$*"
+    if [ -z "$args" ]; then
+        args="all"
+    fi
+
+    if [[ "$args" =~ (recorder|all) ]]; then
+        echo 'Released recorder'
+        PROMPT_COMMAND="${PROMPT_COMMAND#_scribe-finisher}"
+        PROMPT_COMMAND="${PROMPT_COMMAND#; }"
+        PROMPT_COMMAND="${PROMPT_COMMAND%_scribe-ready}"
+        PROMPT_COMMAND="${PROMPT_COMMAND%; }"
+        trap - DEBUG
+        eval "$_SCRIBE_PREV_DEBUG_TRAP"
+    fi
+
+    if [[ "$args" =~ (search|all) ]]; then
+        echo 'Released search'
+        bind -r '\C-r'
+        if [ -n "$_SCRIBE_PREV_HISTORY_SEARCH" ]; then
+            bind "$_SCRIBE_PREV_HISTORY_SEARCH"
+        fi
+    fi
+}
@@ -3,19 +3,49 @@
 # Proprietary
 # Updated by Brandon Waite, May 28 2020
 
+set -g _SCRIBE_SESSION (scribe session)
+set -g _SCRIBE_PREV_HISTORY_SEARCH (bind \cr 2>/dev/null)
+
 function _scribe-recorder --on-event fish_preexec
-    _scribe-release
+    set -g _SCRIBE_CWD $PWD
+    set -g _SCRIBE_START (date +%s)
+end
+
+function _scribe-finisher --on-event fish_postexec
+    set -l code $status
+    if not set -q _SCRIBE_START
+        return
+    end
+
+    set -l end (math --scale 0 "$_SCRIBE_START + $CMD_DURATION / 1000")
+    set -l cmd (scribe record --exit $code --start $_SCRIBE_START --end $end \
+        --cwd "$_SCRIBE_CWD" --session "$_SCRIBE_SESSION" -- $argv[1])
+    set -e _SCRIBE_CWD _SCRIBE_START
+    if contains -- "$cmd" release release-hooks
+        _scribe-release
+    end
+end
+
+function _scribe-history
+    set -l result (scribe search --interactive --session "$_SCRIBE_SESSION" | string collect)
+    commandline -r -- "$result"
+    commandline -f repaint
 end
+bind \cr _scribe-history
 
 function _scribe-release
     if [ -z "$argv" ]
         set argv "all"
     end
-    if string match $argv "(recorder|all)"
-        function _scribe-recorder
-        end
+    if string match -qr '(recorder|all)' -- $argv
+        echo 'Released recorder'
+        functions -e _scribe-recorder _scribe-finisher
     end
-    if string match $argv "(search|all)"
-        # TODO
+    if string match -qr '(search|all)' -- $argv
+        echo 'Released search'
+        bind -e \cr
+        for binding in $_SCRIBE_PREV_HISTORY_SEARCH
+            eval $binding
+        end
     end
 end
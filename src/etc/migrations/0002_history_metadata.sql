@@ -0,0 +1,11 @@
+-- Copyright (C) Brandon Waite 2020  - All Rights Reserved
+-- Unauthorized copying of this file, via any medium, is strictly prohibited
+-- Proprietary
+-- Updated by Brandon Waite, May 28 2020
+
+ALTER TABLE history ADD COLUMN end_timestamp DATETIME;
+ALTER TABLE history ADD COLUMN exit_code INTEGER;
+ALTER TABLE history ADD COLUMN cwd TEXT;
+ALTER TABLE history ADD COLUMN hostname TEXT;
+ALTER TABLE history ADD COLUMN user TEXT;
+ALTER TABLE history ADD COLUMN session TEXT;
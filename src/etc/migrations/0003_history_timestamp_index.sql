@@ -3,4 +3,4 @@
 -- Proprietary
 -- Updated by Brandon Waite, May 28 2020
 
-CREATE TABLE history (command TEXT, timestamp DATETIME);
\ No newline at end of file
+CREATE INDEX history_timestamp ON history(timestamp);
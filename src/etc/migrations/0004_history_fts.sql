@@ -0,0 +1,25 @@
+-- Copyright (C) Brandon Waite 2020  - All Rights Reserved
+-- Unauthorized copying of this file, via any medium, is strictly prohibited
+-- Proprietary
+-- Updated by Brandon Waite, May 28 2020
+
+CREATE VIRTUAL TABLE history_fts USING fts5(
+    command,
+    content = 'history',
+    tokenize = 'trigram case_sensitive 1'
+);
+
+CREATE TRIGGER history_fts_insert AFTER INSERT ON history BEGIN
+    INSERT INTO history_fts(rowid, command) VALUES (new.rowid, new.command);
+END;
+
+CREATE TRIGGER history_fts_delete AFTER DELETE ON history BEGIN
+    INSERT INTO history_fts(history_fts, rowid, command) VALUES ('delete', old.rowid, old.command);
+END;
+
+CREATE TRIGGER history_fts_update AFTER UPDATE OF command ON history BEGIN
+    INSERT INTO history_fts(history_fts, rowid, command) VALUES ('delete', old.rowid, old.command);
+    INSERT INTO history_fts(rowid, command) VALUES (new.rowid, new.command);
+END;
+
+INSERT INTO history_fts(history_fts) VALUES ('rebuild');
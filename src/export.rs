@@ -0,0 +1,194 @@
+use std::convert::From;
+use std::io::Write;
+
+use rusqlite::ToSql;
+
+use super::dates;
+use super::history::Entry;
+use super::init::DataStores;
+use super::record;
+
+pub struct ExportError {
+    pub cause: String,
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(err: std::io::Error) -> Self {
+        ExportError{ cause: format!("IO Error: {}", err) }
+    }
+}
+
+impl From<rusqlite::Error> for ExportError {
+    fn from(err: rusqlite::Error) -> Self {
+        ExportError{ cause: format!("Underlying IndexSQL (sqlite3) error occured: {:?}", err) }
+    }
+}
+
+impl From<serde_json::Error> for ExportError {
+    fn from(err: serde_json::Error) -> Self {
+        ExportError{ cause: format!("Unable to encode JSON: {}", err) }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Format {
+    JsonLines,
+    Csv,
+    Zsh,
+    Bash,
+    Fish,
+}
+
+pub const FORMATS: [&str; 5] = ["jsonl", "csv", "zsh", "bash", "fish"];
+
+impl Format {
+    pub fn parse(name: &str) -> Result<Self, ExportError> {
+        match name {
+            "jsonl" => Ok(Format::JsonLines),
+            "csv" => Ok(Format::Csv),
+            "zsh" => Ok(Format::Zsh),
+            "bash" => Ok(Format::Bash),
+            "fish" => Ok(Format::Fish),
+            _ => Err(ExportError{ cause: format!("Unknown export format '{}', expected one of {}", name, FORMATS.join(", ")) }),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Filters {
+    pub since: Option<u32>,
+    pub until: Option<u32>,
+    pub host: Option<String>,
+    pub cwd: Option<String>,
+}
+
+impl Filters {
+    /// Parses `--since/--until/--host/--cwd` flags, returning anything it did not recognize
+    pub fn parse(flags: &[String], now: u32) -> Result<(Self, Vec<String>), ExportError> {
+        let mut filters = Filters::default();
+        let mut rest = vec![];
+        let mut args = flags.iter();
+        while let Some(arg) = args.next() {
+            let flag = arg.as_str();
+            if !["--since", "--until", "--host", "--cwd"].contains(&flag) {
+                rest.push(arg.clone());
+                continue;
+            }
+
+            let value = args.next().ok_or(ExportError{ cause: format!("Missing value for '{}'", flag) })?;
+            let date = || dates::parse(value, now).map_err(|e| ExportError{ cause: format!("Invalid {}: {}", flag, e) });
+            match flag {
+                "--since" => filters.since = Some(date()?),
+                "--until" => filters.until = Some(date()?),
+                "--host" => filters.host = Some(value.clone()),
+                _ => filters.cwd = Some(value.trim_end_matches('/').to_owned()),
+            }
+        }
+        Ok((filters, rest))
+    }
+
+    fn clauses(&self) -> (String, Vec<(&'static str, &dyn ToSql)>) {
+        let mut clauses = vec!["1 = 1"];
+        let mut params: Vec<(&'static str, &dyn ToSql)> = vec![];
+        if let Some(since) = self.since.as_ref() {
+            clauses.push("timestamp >= :since");
+            params.push((":since", since));
+        }
+        if let Some(until) = self.until.as_ref() {
+            clauses.push("timestamp < :until");
+            params.push((":until", until));
+        }
+        if let Some(host) = self.host.as_ref() {
+            clauses.push("hostname = :host");
+            params.push((":host", host));
+        }
+        if let Some(cwd) = self.cwd.as_ref() {
+            // the directory itself and everything below it
+            clauses.push("(cwd = :cwd OR substr(cwd, 1, length(:cwd) + 1) = :cwd || '/')");
+            params.push((":cwd", cwd));
+        }
+        (clauses.join(" AND "), params)
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+fn csv_number<T: ToString>(field: Option<T>) -> String {
+    field.map(|f| f.to_string()).unwrap_or_default()
+}
+
+/// Inverse of the import side: bytes zsh reserves are written as Meta followed by the byte xor 32
+fn metafy(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    for &b in text.as_bytes() {
+        if b == 0 || (0x83..=0xa2).contains(&b) {
+            bytes.push(0x83);
+            bytes.push(b ^ 32);
+        } else {
+            bytes.push(b);
+        }
+    }
+    bytes
+}
+
+fn write_entry(writer: &mut dyn Write, format: Format, entry: &Entry) -> Result<(), ExportError> {
+    match format {
+        Format::JsonLines => {
+            writeln!(writer, "{}", serde_json::to_string(entry)?)?;
+        }
+        Format::Csv => {
+            writeln!(writer, "{},{},{},{},{},{},{},{}",
+                entry.timestamp,
+                csv_number(entry.end_timestamp),
+                csv_number(entry.exit_code),
+                csv_field(entry.cwd.as_deref().unwrap_or_default()),
+                csv_field(entry.hostname.as_deref().unwrap_or_default()),
+                csv_field(entry.user.as_deref().unwrap_or_default()),
+                csv_field(entry.session.as_deref().unwrap_or_default()),
+                csv_field(&entry.command),
+            )?;
+        }
+        Format::Zsh => {
+            let elapsed = entry.end_timestamp.map(|end| end.saturating_sub(entry.timestamp)).unwrap_or(0);
+            write!(writer, ": {}:{};", entry.timestamp, elapsed)?;
+            writer.write_all(&metafy(&entry.command.replace('\n', "\\\n")))?;
+            writeln!(writer)?;
+        }
+        Format::Bash => {
+            writeln!(writer, "#{}\n{}", entry.timestamp, entry.command)?;
+        }
+        Format::Fish => {
+            writeln!(writer, "- cmd: {}\n  when: {}", entry.command.replace('\\', "\\\\").replace('\n', "\\n"), entry.timestamp)?;
+        }
+    }
+    Ok(())
+}
+
+/// Streams matching history rows oldest first, returning how many were written
+pub fn export(deps: DataStores, format: Format, filters: &Filters, writer: &mut dyn Write) -> Result<usize, ExportError> {
+    let index = deps.index.try_lock().unwrap();
+    let (clauses, params) = filters.clauses();
+    let mut statement = index.prepare(&format!(
+        "SELECT {} FROM history WHERE {} ORDER BY timestamp ASC, oid ASC",
+        record::ENTRY_COLUMNS, clauses,
+    ))?;
+
+    if let Format::Csv = format {
+        writeln!(writer, "timestamp,end_timestamp,exit_code,cwd,hostname,user,session,command")?;
+    }
+
+    let mut rows = statement.query(params.as_slice())?;
+    let mut count = 0;
+    while let Some(row) = rows.next()? {
+        write_entry(writer, format, &record::row_to_entry(row, 0)?)?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
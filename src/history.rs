@@ -0,0 +1,249 @@
+// Archive line format shared by the recorder and anything reading `history/`.
+//
+// version=1 lines are `timestamp:base64(command)`.
+// version=2 lines carry the full entry, every free-form field is base64 encoded
+// and unknown values are left empty:
+//   start:end:exit:base64(cwd):base64(hostname):base64(user):base64(session):base64(command)
+//
+// With encoder=xchacha20poly1305 each of those lines is sealed with the key from
+// `crypto` and written as base64(nonce || ciphertext) instead.
+
+pub const VERSION: u32 = 2;
+
+use serde::{Deserialize, Serialize};
+
+use super::crypto::Key;
+
+/// How the lines after the header are written, named by the header's `encoder` field
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Encoder {
+    Base64,
+    XChaCha20Poly1305,
+}
+
+impl Encoder {
+    /// The encoder used for new lines, sealed whenever a key is configured
+    pub fn of(key: Option<&Key>) -> Self {
+        match key {
+            Some(_) => Encoder::XChaCha20Poly1305,
+            None => Encoder::Base64,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Encoder::Base64 => "base64",
+            Encoder::XChaCha20Poly1305 => "xchacha20poly1305",
+        }
+    }
+
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "base64" => Ok(Encoder::Base64),
+            "xchacha20poly1305" => Ok(Encoder::XChaCha20Poly1305),
+            _ => Err(format!("unsupported archive encoder '{}'", name)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub command: String,
+    pub timestamp: u32,
+    pub end_timestamp: Option<u32>,
+    pub exit_code: Option<i32>,
+    pub cwd: Option<String>,
+    pub hostname: Option<String>,
+    pub user: Option<String>,
+    pub session: Option<String>,
+}
+
+impl Entry {
+    pub fn new(command: String, timestamp: u32) -> Self {
+        Entry{ command, timestamp, ..Default::default() }
+    }
+}
+
+pub fn header(key: Option<&Key>) -> String {
+    format!("version={},encoder={}\n---\n", VERSION, Encoder::of(key).name())
+}
+
+fn encode_field(field: &Option<String>) -> String {
+    field.as_ref().map(|f| base64::encode(f.as_bytes())).unwrap_or_default()
+}
+
+fn encode_number<T: ToString>(field: Option<T>) -> String {
+    field.map(|f| f.to_string()).unwrap_or_default()
+}
+
+/// Encodes an entry as a version 2 line, sealed when a key is given
+pub fn encode_line(entry: &Entry, key: Option<&Key>) -> String {
+    let line = format!("{}:{}:{}:{}:{}:{}:{}:{}",
+        entry.timestamp,
+        encode_number(entry.end_timestamp),
+        encode_number(entry.exit_code),
+        encode_field(&entry.cwd),
+        encode_field(&entry.hostname),
+        encode_field(&entry.user),
+        encode_field(&entry.session),
+        base64::encode(entry.command.as_bytes()),
+    );
+    match key {
+        Some(key) => format!("{}\n", key.seal(line.as_bytes())),
+        None => format!("{}\n", line),
+    }
+}
+
+#[derive(Debug)]
+pub struct Malformed {
+    pub line: usize,
+    pub reason: String,
+}
+
+/// Everything recovered from one archive file, bad lines are collected rather than aborting the read
+#[derive(Debug, Default)]
+pub struct Archive {
+    pub version: u32,
+    pub entries: Vec<Entry>,
+    pub malformed: Vec<Malformed>,
+}
+
+pub fn parse_header(line: &str) -> Result<(u32, Encoder), String> {
+    let mut version = None;
+    let mut encoder = None;
+    for pair in line.split(',') {
+        match pair.split_once('=') {
+            Some(("version", value)) => {
+                version = Some(value.parse::<u32>().map_err(|_| format!("invalid header version '{}'", value))?);
+            }
+            Some(("encoder", value)) => {
+                encoder = Some(value);
+            }
+            _ => {
+                return Err(format!("unrecognized header field '{}'", pair));
+            }
+        }
+    }
+
+    match (version, encoder) {
+        (Some(v), _) if v == 0 || v > VERSION => Err(format!("unsupported archive version {}", v)),
+        (Some(v), Some(e)) => Ok((v, Encoder::parse(e)?)),
+        _ => Err(format!("incomplete archive header '{}'", line)),
+    }
+}
+
+fn decode_text(field: &str, name: &str) -> Result<String, String> {
+    let bytes = base64::decode(field).map_err(|e| format!("{} is not valid base64: {}", name, e))?;
+    String::from_utf8(bytes).map_err(|_| format!("{} is not valid utf-8", name))
+}
+
+fn decode_field(field: &str, name: &str) -> Result<Option<String>, String> {
+    if field.is_empty() {
+        Ok(None)
+    } else {
+        decode_text(field, name).map(Some)
+    }
+}
+
+fn decode_number<T: std::str::FromStr>(field: &str, name: &str) -> Result<Option<T>, String> {
+    if field.is_empty() {
+        Ok(None)
+    } else {
+        field.parse().map(Some).map_err(|_| format!("{} '{}' is not a number", name, field))
+    }
+}
+
+/// Decodes a single archive line, the field count tells version 1 and 2 lines apart
+pub fn decode_line(line: &str) -> Result<Entry, String> {
+    let fields: Vec<&str> = line.split(':').collect();
+    let timestamp = decode_number(fields[0], "timestamp")?.ok_or("timestamp is missing")?;
+    match fields.len() {
+        2 => {
+            Ok(Entry::new(decode_text(fields[1], "command")?, timestamp))
+        }
+        8 => {
+            Ok(Entry{
+                command: decode_text(fields[7], "command")?,
+                timestamp,
+                end_timestamp: decode_number(fields[1], "end timestamp")?,
+                exit_code: decode_number(fields[2], "exit code")?,
+                cwd: decode_field(fields[3], "cwd")?,
+                hostname: decode_field(fields[4], "hostname")?,
+                user: decode_field(fields[5], "user")?,
+                session: decode_field(fields[6], "session")?,
+            })
+        }
+        n => {
+            Err(format!("expected 2 or 8 fields but found {}", n))
+        }
+    }
+}
+
+/// Decodes a line written by `encoder`, sealed lines need the key they were written with
+pub fn decode(line: &str, encoder: Encoder, key: Option<&Key>) -> Result<Entry, String> {
+    match (encoder, key) {
+        (Encoder::Base64, _) => decode_line(line),
+        (Encoder::XChaCha20Poly1305, Some(key)) => {
+            let opened = String::from_utf8(key.open(line)?).map_err(|_| "decrypted line is not valid utf-8".to_owned())?;
+            decode_line(&opened)
+        }
+        (Encoder::XChaCha20Poly1305, None) => Err("archive is encrypted but no key is configured".to_owned()),
+    }
+}
+
+pub fn read_archive<R: std::io::BufRead>(reader: R, key: Option<&Key>) -> Result<Archive, std::io::Error> {
+    let mut archive = Archive::default();
+    let mut in_body = false;
+    let mut encoder = Encoder::Base64;
+
+    for (number, line) in reader.split(b'\n').enumerate() {
+        let line = line?;
+        let number = number + 1;
+        let line = match String::from_utf8(line) {
+            Ok(line) => line,
+            Err(_) => {
+                archive.malformed.push(Malformed{ line: number, reason: "line is not valid utf-8".to_owned() });
+                continue;
+            }
+        };
+
+        if !in_body {
+            if number == 1 {
+                match (parse_header(&line), decode_line(&line)) {
+                    (Ok((version, found)), _) if found == Encoder::XChaCha20Poly1305 && key.is_none() => {
+                        // every line would fail the same way, report it once
+                        archive.version = version;
+                        archive.malformed.push(Malformed{ line: number, reason: "archive is encrypted but no key is configured".to_owned() });
+                        return Ok(archive);
+                    }
+                    (Ok((version, found)), _) => {
+                        archive.version = version;
+                        encoder = found;
+                    }
+                    (Err(_), Ok(entry)) => {
+                        archive.malformed.push(Malformed{ line: number, reason: "archive header is missing".to_owned() });
+                        archive.entries.push(entry);
+                        in_body = true;
+                    }
+                    (Err(reason), Err(_)) => {
+                        archive.malformed.push(Malformed{ line: number, reason });
+                    }
+                }
+            } else if line == "---" {
+                in_body = true;
+            } else {
+                archive.malformed.push(Malformed{ line: number, reason: "expected '---' after the header".to_owned() });
+            }
+            continue;
+        }
+
+        if line.is_empty() {
+            continue;
+        }
+        match decode(&line, encoder, key) {
+            Ok(entry) => archive.entries.push(entry),
+            Err(reason) => archive.malformed.push(Malformed{ line: number, reason }),
+        }
+    }
+    Ok(archive)
+}
@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+use std::convert::From;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use rusqlite::named_params;
+
+use super::archive;
+use super::crypto;
+use super::history::{Entry, Malformed};
+use super::init::{self, DataStores};
+use super::record;
+
+pub struct ImportError {
+    pub cause: String,
+}
+
+impl From<std::io::Error> for ImportError {
+    fn from(err: std::io::Error) -> Self {
+        ImportError{ cause: format!("IO Error: {}", err) }
+    }
+}
+
+impl From<rusqlite::Error> for ImportError {
+    fn from(err: rusqlite::Error) -> Self {
+        ImportError{ cause: format!("Underlying IndexSQL (sqlite3) error occured: {:?}", err) }
+    }
+}
+
+impl From<archive::ArchiveError> for ImportError {
+    fn from(err: archive::ArchiveError) -> Self {
+        ImportError{ cause: err.cause }
+    }
+}
+
+impl From<crypto::CryptoError> for ImportError {
+    fn from(err: crypto::CryptoError) -> Self {
+        ImportError{ cause: err.cause }
+    }
+}
+
+impl From<record::RecordError> for ImportError {
+    fn from(err: record::RecordError) -> Self {
+        ImportError{ cause: err.cause }
+    }
+}
+
+#[derive(Default)]
+pub struct Parsed {
+    pub entries: Vec<Entry>,
+    pub malformed: Vec<Malformed>,
+}
+
+impl From<Vec<Entry>> for Parsed {
+    fn from(entries: Vec<Entry>) -> Self {
+        Parsed{ entries, malformed: vec![] }
+    }
+}
+
+#[derive(Default)]
+pub struct Summary {
+    pub imported: usize,
+    pub skipped: usize,
+    pub malformed: Vec<Malformed>,
+}
+
+/// A source of history entries, `fallback` stamps entries whose format has no timestamps
+pub trait Importer {
+    fn name(&self) -> &'static str;
+    /// Where the source normally lives on this machine, if it has a conventional location
+    fn default_path(&self) -> Option<PathBuf>;
+    fn parse(&self, path: &Path, fallback: u32) -> Result<Parsed, ImportError>;
+}
+
+pub const FORMATS: [&str; 5] = ["zsh", "bash", "fish", "scribe-archive", "jsonl"];
+
+pub fn importer(format: &str) -> Result<Box<dyn Importer>, ImportError> {
+    match format {
+        "zsh" => Ok(Box::new(Zsh)),
+        "bash" => Ok(Box::new(Bash)),
+        "fish" => Ok(Box::new(Fish)),
+        "scribe-archive" => Ok(Box::new(ScribeArchive)),
+        "jsonl" => Ok(Box::new(JsonLines)),
+        _ => Err(ImportError{ cause: format!("Unknown import format '{}', expected one of {}", format, FORMATS.join(", ")) }),
+    }
+}
+
+fn histfile_or(name: &str) -> Option<PathBuf> {
+    std::env::var("HISTFILE").map(PathBuf::from).ok().or_else(|| dirs::home_dir().map(|home| home.join(name)))
+}
+
+fn open(path: &Path) -> Result<BufReader<std::fs::File>, ImportError> {
+    Ok(BufReader::new(std::fs::File::open(path)?))
+}
+
+/// zsh "metafies" bytes it treats specially by writing Meta (0x83) followed by the byte xor 32
+const ZSH_META: u8 = 0x83;
+
+fn unmetafy(bytes: &[u8]) -> Vec<u8> {
+    let mut plain = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter();
+    while let Some(&b) = iter.next() {
+        if b == ZSH_META {
+            if let Some(&next) = iter.next() {
+                plain.push(next ^ 32);
+            }
+        } else {
+            plain.push(b);
+        }
+    }
+    plain
+}
+
+/// Splits the `: <start>:<elapsed>;` prefix written with EXTENDED_HISTORY off a command
+fn zsh_extended(line: &str) -> Option<(u32, u32, &str)> {
+    let rest = line.strip_prefix(": ")?;
+    let (times, command) = rest.split_once(';')?;
+    let (start, elapsed) = times.split_once(':')?;
+    Some((start.trim().parse().ok()?, elapsed.trim().parse().ok()?, command))
+}
+
+fn zsh_entry(raw: &[u8], fallback: u32) -> Option<Entry> {
+    let text = String::from_utf8_lossy(&unmetafy(raw)).into_owned();
+    let entry = match zsh_extended(&text) {
+        Some((start, elapsed, command)) => {
+            let mut entry = Entry::new(command.to_owned(), start);
+            entry.end_timestamp = Some(start + elapsed);
+            entry
+        }
+        None => Entry::new(text, fallback),
+    };
+    Some(entry).filter(|e| !e.command.is_empty())
+}
+
+/// Parses `~/.zsh_history`, handling EXTENDED_HISTORY prefixes, metafied bytes and
+/// multi-line commands, which zsh stores with a trailing backslash before each newline.
+/// Plain entries without a prefix are stamped with `fallback`.
+fn parse_zsh_history<R: BufRead>(reader: R, fallback: u32) -> Result<Vec<Entry>, std::io::Error> {
+    let mut entries = vec![];
+    let mut pending: Vec<u8> = vec![];
+
+    for line in reader.split(b'\n') {
+        let line = line?;
+        if line.ends_with(b"\\") {
+            pending.extend_from_slice(&line[..line.len() - 1]);
+            pending.push(b'\n');
+            continue;
+        }
+        pending.extend_from_slice(&line);
+        entries.extend(zsh_entry(&pending, fallback));
+        pending.clear();
+    }
+    entries.extend(zsh_entry(&pending, fallback));
+    Ok(entries)
+}
+
+fn bash_timestamp(line: &str) -> Option<u32> {
+    let digits = line.strip_prefix('#')?;
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// Parses `~/.bash_history`. When bash saved `#<epoch>` lines (HISTTIMEFORMAT was set),
+/// everything up to the next timestamp is one entry, which keeps multi-line commands intact.
+/// Without timestamps every line is an entry stamped with `fallback`.
+fn parse_bash_history<R: BufRead>(reader: R, fallback: u32) -> Result<Vec<Entry>, std::io::Error> {
+    let mut entries: Vec<Entry> = vec![];
+    let mut timestamp = None;
+    let mut pending: Option<Entry> = None;
+
+    for line in reader.split(b'\n') {
+        let line = String::from_utf8_lossy(&line?).into_owned();
+        if let Some(ts) = bash_timestamp(&line) {
+            entries.extend(pending.take());
+            timestamp = Some(ts);
+            continue;
+        }
+
+        match (timestamp, pending.as_mut()) {
+            (Some(_), Some(entry)) => {
+                entry.command.push('\n');
+                entry.command.push_str(&line);
+            }
+            (Some(ts), None) => {
+                pending = Some(Entry::new(line, ts));
+            }
+            (None, _) if !line.is_empty() => {
+                entries.push(Entry::new(line, fallback));
+            }
+            (None, _) => {}
+        }
+    }
+    entries.extend(pending.take());
+    entries.retain(|e| !e.command.is_empty());
+    Ok(entries)
+}
+
+/// Reverses the escaping fish applies to `cmd:` values, only `\\` and `\n` are escaped
+fn unescape_fish(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('\\')) => {
+                unescaped.push('\\');
+                chars.next();
+            }
+            ('\\', Some('n')) => {
+                unescaped.push('\n');
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Parses fish's YAML-like history file:
+///
+/// ```text
+/// - cmd: git status
+///   when: 1589000000
+///   paths:
+///     - src/
+/// ```
+///
+/// `paths:` lists files referenced by the command, not the working directory, so they are skipped.
+fn parse_fish_history<R: BufRead>(reader: R, fallback: u32) -> Result<Vec<Entry>, std::io::Error> {
+    let mut entries: Vec<Entry> = vec![];
+    for line in reader.split(b'\n') {
+        let line = String::from_utf8_lossy(&line?).into_owned();
+        if let Some(cmd) = line.strip_prefix("- cmd: ") {
+            entries.push(Entry::new(unescape_fish(cmd), fallback));
+        } else if let Some(when) = line.trim_start().strip_prefix("when: ") {
+            match (entries.last_mut(), when.trim().parse()) {
+                (Some(entry), Ok(timestamp)) => entry.timestamp = timestamp,
+                _ => log::warn!("Ignoring fish history timestamp '{}'", when),
+            }
+        }
+    }
+    entries.retain(|e| !e.command.is_empty());
+    Ok(entries)
+}
+
+struct Zsh;
+
+impl Importer for Zsh {
+    fn name(&self) -> &'static str {
+        "zsh"
+    }
+
+    fn default_path(&self) -> Option<PathBuf> {
+        histfile_or(".zsh_history")
+    }
+
+    fn parse(&self, path: &Path, fallback: u32) -> Result<Parsed, ImportError> {
+        Ok(parse_zsh_history(open(path)?, fallback)?.into())
+    }
+}
+
+struct Bash;
+
+impl Importer for Bash {
+    fn name(&self) -> &'static str {
+        "bash"
+    }
+
+    fn default_path(&self) -> Option<PathBuf> {
+        histfile_or(".bash_history")
+    }
+
+    fn parse(&self, path: &Path, fallback: u32) -> Result<Parsed, ImportError> {
+        Ok(parse_bash_history(open(path)?, fallback)?.into())
+    }
+}
+
+struct Fish;
+
+impl Importer for Fish {
+    fn name(&self) -> &'static str {
+        "fish"
+    }
+
+    fn default_path(&self) -> Option<PathBuf> {
+        // fish uses the XDG layout on every platform, including macOS
+        let data = std::env::var("XDG_DATA_HOME").map(PathBuf::from).ok()
+            .or_else(|| dirs::home_dir().map(|home| home.join(".local").join("share")))?;
+        Some(data.join("fish").join("fish_history"))
+    }
+
+    fn parse(&self, path: &Path, fallback: u32) -> Result<Parsed, ImportError> {
+        Ok(parse_fish_history(open(path)?, fallback)?.into())
+    }
+}
+
+/// Another machine's archive, either a single (possibly compressed) file or a whole scribe dir
+struct ScribeArchive;
+
+impl Importer for ScribeArchive {
+    fn name(&self) -> &'static str {
+        "scribe-archive"
+    }
+
+    fn default_path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    fn parse(&self, path: &Path, _fallback: u32) -> Result<Parsed, ImportError> {
+        // a scribe dir brings its own key, a lone file is expected to share ours
+        let (files, key) = if path.join("history").is_dir() {
+            (archive::archive_files(path)?, crypto::load(path)?)
+        } else {
+            let home = init::scribe_dir().map_err(|e| ImportError{ cause: e.cause })?;
+            (vec![path.to_path_buf()], crypto::load(&home)?)
+        };
+
+        let mut parsed = Parsed::default();
+        for file in files {
+            let archive = archive::read_file(&file, key.as_ref())?;
+            parsed.entries.extend(archive.entries);
+            parsed.malformed.extend(archive.malformed);
+        }
+        Ok(parsed)
+    }
+}
+
+/// One JSON object per line with the same fields as `history::Entry`
+struct JsonLines;
+
+impl Importer for JsonLines {
+    fn name(&self) -> &'static str {
+        "jsonl"
+    }
+
+    fn default_path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    fn parse(&self, path: &Path, _fallback: u32) -> Result<Parsed, ImportError> {
+        let mut parsed = Parsed::default();
+        for (number, line) in open(path)?.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Entry>(&line) {
+                Ok(entry) => parsed.entries.push(entry),
+                Err(e) => parsed.malformed.push(Malformed{ line: number + 1, reason: e.to_string() }),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Given to importers as the fallback so entries without a timestamp of their own stand out
+const UNTIMED: u32 = 0;
+
+/// Rows holding the command of `entry`, at its `timestamp` unless the source had none
+fn indexed(index: &rusqlite::Connection, entry: &Entry, timestamp: Option<u32>) -> Result<usize, rusqlite::Error> {
+    match timestamp {
+        Some(timestamp) => index.query_row(
+            "SELECT count(*) FROM history WHERE timestamp = :timestamp AND command = :command",
+            named_params!{
+                ":timestamp": timestamp,
+                ":command": entry.command,
+            },
+            |row| row.get(0),
+        ),
+        None => index.query_row(
+            "SELECT count(*) FROM history WHERE command = :command",
+            named_params!{ ":command": entry.command },
+            |row| row.get(0),
+        ),
+    }
+}
+
+/// Imports everything `importer` finds at `path` so the same source can be imported repeatedly.
+/// The nth occurrence of a (timestamp, command) in the source is skipped when the index already
+/// holds n of them, which keeps commands repeated within a source that has no timestamps.
+/// Entries without a timestamp are stamped with the file's mtime, which changes whenever the
+/// shell saves its history, so they are matched on the command alone.
+pub fn import(deps: DataStores, importer: &dyn Importer, path: &Path) -> Result<Summary, ImportError> {
+    let modified = std::fs::metadata(path)?.modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .or_else(|_| record::now())?;
+    let parsed = importer.parse(path, UNTIMED)?;
+    let redactor = record::Redactor::load(&deps.home)?;
+
+    let mut summary = Summary{ malformed: parsed.malformed, ..Default::default() };
+    let mut occurrences: HashMap<(Option<u32>, String), usize> = HashMap::new();
+    deps.index.try_lock().unwrap().execute_batch("BEGIN")?;
+    let result = parsed.entries.into_iter().try_for_each(|mut entry| {
+        let timestamp = Some(entry.timestamp).filter(|&t| t != UNTIMED);
+        entry.timestamp = timestamp.unwrap_or(modified);
+        // redacted before the duplicate check so importing the same source again matches
+        redactor.apply(&mut entry);
+        let occurrence = occurrences.entry((timestamp, entry.command.clone())).or_insert(0);
+        *occurrence += 1;
+        // earlier occurrences from this source are indexed by now, so only older rows can reach n
+        if indexed(&deps.index.try_lock().unwrap(), &entry, timestamp)? >= *occurrence {
+            summary.skipped += 1;
+        } else {
+            record::append_history(deps.clone(), &entry)?;
+            summary.imported += 1;
+        }
+        Ok::<(), ImportError>(())
+    });
+    // entries already appended to the archive must stay indexed even if a later one failed
+    deps.index.try_lock().unwrap().execute_batch("COMMIT")?;
+    result?;
+
+    log::info!("Imported {} entries from {} history '{}', skipped {} duplicates and {} malformed lines",
+        summary.imported, importer.name(), path.display(), summary.skipped, summary.malformed.len());
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    /// A scribe dir of its own under the temp dir, removed on drop
+    struct Home(PathBuf);
+
+    impl Home {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("scribe-test-{}-{}", name, std::process::id()));
+            for dir in init::dirs() {
+                std::fs::create_dir_all(path.join(dir)).unwrap();
+            }
+            Home(path)
+        }
+    }
+
+    impl Drop for Home {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn rows(deps: &DataStores) -> usize {
+        deps.index.try_lock().unwrap().query_row("SELECT count(*) FROM history", named_params!{}, |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn reimporting_an_untimed_history_adds_nothing() {
+        let home = Home::new("untimed");
+        let deps = init::deps(home.0.clone()).unwrap_or_else(|e| panic!("{}", e.cause));
+        let path = home.0.join("bash_history");
+        std::fs::write(&path, "ls\ngit status\nls\n").unwrap();
+        let importer = importer("bash").unwrap_or_else(|e| panic!("{}", e.cause));
+
+        let summary = import(deps.clone(), importer.as_ref(), &path).unwrap_or_else(|e| panic!("{}", e.cause));
+        assert_eq!((summary.imported, rows(&deps)), (3, 3));
+
+        // the shell saving its history moves the mtime the entries were stamped with
+        let later = SystemTime::now() + Duration::from_secs(3600);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        let summary = import(deps.clone(), importer.as_ref(), &path).unwrap_or_else(|e| panic!("{}", e.cause));
+        assert_eq!((summary.imported, summary.skipped, rows(&deps)), (0, 3, 3));
+    }
+}
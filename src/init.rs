@@ -2,13 +2,15 @@ use std::convert::From;
 use std::error::Error;
 use std::env::VarError;
 use std::fmt;
-use std::fs::{File, OpenOptions};
-use std::io::Write;
-use std::io::{prelude::*, BufReader};
+use std::fs::File;
 
 use rusqlite::named_params;
 
-use super::record;
+use super::archive;
+use super::crypto::{self, Key};
+use super::import;
+use super::migrate;
+use super::search;
 
 #[derive(Debug)]
 pub struct InitError {
@@ -42,6 +44,24 @@ impl From<std::io::Error> for InitError {
     }
 }
 
+impl From<archive::ArchiveError> for InitError {
+    fn from(err: archive::ArchiveError) -> Self {
+        InitError{ cause: format!("Unable to prepare the archive: {}", err.cause) }
+    }
+}
+
+impl From<crypto::CryptoError> for InitError {
+    fn from(err: crypto::CryptoError) -> Self {
+        InitError{ cause: err.cause }
+    }
+}
+
+impl From<migrate::MigrateError> for InitError {
+    fn from(err: migrate::MigrateError) -> Self {
+        InitError{ cause: format!("Unable to migrate the index: {}", err.cause) }
+    }
+}
+
 impl From<rusqlite::Error> for InitError {
     fn from(err: rusqlite::Error) -> Self {
         InitError {
@@ -50,6 +70,7 @@ impl From<rusqlite::Error> for InitError {
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 enum Shell {
     ZSH,
     FISH,
@@ -104,83 +125,81 @@ pub fn env_init() -> Result<(), InitError> {
 }
 
 pub struct DataStores {
+    pub home: std::path::PathBuf,
     pub index: std::sync::Arc<std::sync::Mutex<rusqlite::Connection>>,
     pub archive: File,
+    /// whether `history_fts` exists so searches can use the trigram index
+    pub fts: bool,
+    /// set when the scribe dir is encrypted, archive lines are sealed with it
+    pub key: Option<Key>,
 }
 
 impl std::clone::Clone for DataStores {
     fn clone(&self) -> Self {
         DataStores{
+            home: self.home.clone(),
             index: self.index.clone(),
             archive: self.archive.try_clone().unwrap(),
+            fts: self.fts,
+            key: self.key.clone(),
         }
     }
 }
 
-pub fn deps(home: std::path::PathBuf) -> Result<DataStores, InitError> {
+pub fn open_index(home: &std::path::Path, key: Option<&Key>) -> Result<rusqlite::Connection, InitError> {
     let index = rusqlite::Connection::open(home.join("data").join("index.db"))?;
-    index.execute_named("PRAGMA case_sensitive_like=ON", named_params! {})?;
-
-    let latest = home.join("history").join("LATEST");
-    let exists = latest.exists();
-
-    let mut archive = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .create(true)
-        .truncate(false)
-        .open(latest)?;
+    crypto::unlock(&index, key)?;
+    index.execute("PRAGMA case_sensitive_like=ON", named_params! {})?;
+    search::register_functions(&index)?;
+    Ok(index)
+}
 
-    if !exists {
-        archive.write(format!("version=1,encoder=base64\n---\n").as_bytes())?;
+/// Opens the index without creating, migrating or writing to it, None when there is none yet
+pub fn open_index_read_only(home: &std::path::Path, key: Option<&Key>) -> Result<Option<rusqlite::Connection>, InitError> {
+    let path = home.join("data").join("index.db");
+    if !path.exists() {
+        return Ok(None);
     }
+    let index = rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    crypto::unlock(&index, key)?;
+    Ok(Some(index))
+}
 
-    let exec = index.execute_named(include_str!("etc/schema.sql"), named_params![]);
-    if let Err(rusqlite::Error::SqliteFailure(code, Some(msg))) = exec {
-        if msg != "table history already exists" {
-            return Err(rusqlite::Error::SqliteFailure(code, Some(msg)).into());
-        }
-    } else {
-        exec.map(|_| ())?;
-    }
+pub fn deps(home: std::path::PathBuf) -> Result<DataStores, InitError> {
+    let key = crypto::load(&home)?;
+    let mut index = open_index(&home, key.as_ref())?;
+    migrate::migrate(&mut index)?;
+    let fts = search::fts_available(&index)?;
+
+    archive::rotate(&home, None, key.as_ref())?;
+    let archive = archive::open_latest(&home, key.as_ref())?;
 
     Ok(DataStores{
+        home,
         index: std::sync::Arc::new(std::sync::Mutex::new(index)),
         archive,
+        fts,
+        key,
     })
 }
 
-fn import_zsh_history(deps: DataStores) -> Result<(), InitError> {
-    let home = dirs::home_dir().ok_or(InitError{
-        cause: format!("$HOME was not set while trying to import zsh history into the index")
-    })?;
-    let histfile = home.join(".zsh_history");
-
-    if !histfile.exists() {
-        log::warn!("ZSH_history file was not found on import attemp");
-        return Ok(());
-    }
-
-    let history = std::fs::File::open(histfile)?;
-    let reader = BufReader::new(history);
-    for line in reader.lines() {
-        record::append_history(deps.clone(), line?).or_else(|e| Err(InitError{
-            cause: format!("Unable to complete import from ZSH history: {}", e.cause)
-        }))?;
-    }
-    Ok(())
-}
-
 pub fn import_history(deps: DataStores) -> Result<(), InitError>  {
-    match current_shell()? {
-        Shell::ZSH => {
-            import_zsh_history(deps)
-        }
-        Shell::FISH => {
-            Ok(())
+    let format = match current_shell()? {
+        Shell::ZSH => "zsh",
+        Shell::FISH => "fish",
+        Shell::BASH => "bash",
+    };
+    let importer = import::importer(format).map_err(|e| InitError{ cause: e.cause })?;
+
+    match importer.default_path() {
+        Some(path) if path.exists() => {
+            import::import(deps, importer.as_ref(), &path).map_err(|e| InitError{
+                cause: format!("Unable to complete import from {} history: {}", format, e.cause),
+            })?;
         }
-        Shell::BASH => {
-            Ok(())
+        _ => {
+            log::warn!("{} history file was not found on import attempt", format);
         }
     }
-}
\ No newline at end of file
+    Ok(())
+}
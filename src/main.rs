@@ -5,10 +5,22 @@
 
 use std::convert::From;
 
-use termion;
+use termion::raw::IntoRawMode;
 
+mod archive;
+#[cfg(feature = "bench")]
+mod bench;
+mod config;
+mod crypto;
+mod dates;
 mod init;
 mod debug;
+mod export;
+mod history;
+mod import;
+mod migrate;
+mod reindex;
+mod scan;
 mod search;
 mod record;
 
@@ -33,7 +45,32 @@ impl From<std::io::Error> for ScribeError {
 
 impl From<log::SetLoggerError> for ScribeError {
     fn from(_: log::SetLoggerError) -> Self {
-        ScribeError{ text: format!("Unable to configure debug log file") }
+        ScribeError{ text: "Unable to configure debug log file".to_owned() }
+    }
+}
+
+impl From<archive::ArchiveError> for ScribeError {
+    fn from(err: archive::ArchiveError) -> Self {
+        ScribeError{ text: format!("Failure occured during 'archive' command: {}", err.cause) }
+    }
+}
+
+#[cfg(feature = "bench")]
+impl From<bench::BenchError> for ScribeError {
+    fn from(err: bench::BenchError) -> Self {
+        ScribeError{ text: format!("Failure occured during 'bench' command: {}", err.cause) }
+    }
+}
+
+impl From<config::ConfigError> for ScribeError {
+    fn from(err: config::ConfigError) -> Self {
+        ScribeError{ text: format!("Invalid configuration: {}", err.cause) }
+    }
+}
+
+impl From<crypto::CryptoError> for ScribeError {
+    fn from(err: crypto::CryptoError) -> Self {
+        ScribeError{ text: format!("Failure occured while using the encryption key: {}", err.cause) }
     }
 }
 
@@ -43,15 +80,45 @@ impl From<init::InitError> for ScribeError {
     }
 }
 
+impl From<export::ExportError> for ScribeError {
+    fn from(err: export::ExportError) -> Self {
+        ScribeError{ text: format!("Failure occured during 'export' command: {}", err.cause) }
+    }
+}
+
+impl From<import::ImportError> for ScribeError {
+    fn from(err: import::ImportError) -> Self {
+        ScribeError{ text: format!("Failure occured during 'import' command: {}", err.cause) }
+    }
+}
+
+impl From<migrate::MigrateError> for ScribeError {
+    fn from(err: migrate::MigrateError) -> Self {
+        ScribeError{ text: format!("Failure occured during 'db' command: {}", err.cause) }
+    }
+}
+
 impl From<record::RecordError> for ScribeError {
     fn from(err: record::RecordError) -> Self {
         ScribeError{ text: format!("Failure occured during 'record' command: {}", err.cause) }
     }
 }
 
+impl From<reindex::ReindexError> for ScribeError {
+    fn from(err: reindex::ReindexError) -> Self {
+        ScribeError{ text: format!("Failure occured during 'reindex' command: {}", err.cause) }
+    }
+}
+
+impl From<scan::ScanError> for ScribeError {
+    fn from(err: scan::ScanError) -> Self {
+        ScribeError{ text: format!("Failure occured during 'scan-secrets' command: {}", err.cause) }
+    }
+}
+
 impl From<search::SearchError> for ScribeError {
     fn from(err: search::SearchError) -> Self {
-        ScribeError{ text: format!("Failure occured during 'init' command: {}", err.cause) }
+        ScribeError{ text: format!("Failure occured during 'search' command: {}", err.cause) }
     }
 }
 
@@ -68,23 +135,25 @@ fn init() -> Result<bool, ScribeError> {
     Ok(new)
 }
 
-const NAME: &'static str = env!("CARGO_PKG_NAME");
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+const NAME: &str = env!("CARGO_PKG_NAME");
+const VERSION: &str = env!("CARGO_PKG_VERSION");
 #[cfg(target_os = "macos")]
-const PLATFORM: &'static str = "darwin";
+const PLATFORM: &str = "darwin";
 #[cfg(target_os = "linux")]
-const PLATFORM: &'static str = "linux";
+const PLATFORM: &str = "linux";
 
 fn main() -> Result<(), ScribeError> {
     let fresh = init()?;
-    debug::init(init::scribe_dir()?)?;
+    let home = init::scribe_dir()?;
+    let config = config::load(&home);
+    debug::init(home, config.as_ref().map(|c| c.log_level.filter()).unwrap_or(log::LevelFilter::Info))?;
 
     let args: Vec<String> = std::env::args().collect();
     let (_command, full_flags) = args.split_first().ok_or(ScribeError{
         text: format!("Unknown error: Unable to parse program arguments from '{:?}", args),
     })?;
 
-    if full_flags.len() < 1 {
+    if full_flags.is_empty() {
         println!("HELP TEXT TODO");
         return Ok(())
     }
@@ -93,12 +162,31 @@ fn main() -> Result<(), ScribeError> {
         text: format!("Unknown error: Unable to parse program subcommand and arguments from '{:?}", full_flags),
     })?;
 
+    // a broken file must not keep `config` from showing or fixing it, nor keep the shell
+    // hooks from recording until it is fixed
+    let (config, broken) = match config {
+        Ok(config) => (config, None),
+        Err(e) => match subcommand.as_str() {
+            "config" | "record" => {
+                log::warn!("Invalid configuration, using the defaults: {}", e.cause);
+                (config::Config::default(), Some(e))
+            }
+            _ => return Err(e.into()),
+        },
+    };
+
     match subcommand.as_str() {
         "version" => {
-            Ok(println!("{}-{}-v{}", NAME, PLATFORM, VERSION))
+            println!("{}-{}-v{}", NAME, PLATFORM, VERSION);
+            Ok(())
+        }
+        "session" => {
+            println!("{}", record::new_session_id()?);
+            Ok(())
         }
         // TODO split init into two cmds
         "init" | "bind" => {
+            config::write_default(&init::scribe_dir()?)?;
             if fresh {
                 let deps = init::deps(init::scribe_dir()?)?;
                 init::import_history(deps)?;
@@ -106,42 +194,328 @@ fn main() -> Result<(), ScribeError> {
             Ok(init::env_init()?)
         }
         "record" => {
-            let deps = init::deps(init::scribe_dir()?)?;
+            let home = init::scribe_dir()?;
+            let options = record::parse_options(flags)?;
+            let mut entry = options.entry;
+            let precheck = record::precheck(entry.command.clone());
+            let original = entry.command.clone();
+            let findings = record::Redactor::load(&home)?.apply(&mut entry);
+            let rules = record::IgnoreRules::load(&home, entry.cwd.as_deref());
+
+            if options.dry_run {
+                // read only, a dry run must not create, migrate or rotate anything
+                let index = init::open_index_read_only(&home, crypto::load(&home)?.as_ref())?;
+                let ignored = match precheck {
+                    record::Precheck::Append => rules.check(&original, &entry, index.as_ref())?,
+                    _ => None,
+                };
+                match (&precheck, ignored) {
+                    (record::Precheck::Append, None) => println!("Would record: {}", entry.command),
+                    (record::Precheck::Append, Some(rule)) => println!("Would ignore, matched rule {}: {}", rule, entry.command),
+                    _ => println!("Would skip: {}", entry.command),
+                }
+                for finding in findings.iter() {
+                    println!("  {}: {}", finding.detector, &original[finding.start..finding.end]);
+                }
+                return Ok(());
+            }
+
+            // the shell hooks record before every prompt, a locked history is reported once per
+            // session and the command is left out rather than failing each time
+            if matches!(precheck, record::Precheck::Append) && crypto::locked(&home)? {
+                if crypto::first_locked_notice(&home)? {
+                    eprintln!("scribe: the history is locked and commands are not recorded, run 'scribe unlock' to resume");
+                }
+                log::debug!("Not recording while the history is locked");
+                return Ok(());
+            }
 
-            let cmd = flags.join(" ");
-            match record::precheck(cmd.clone()) {
+            let deps = init::deps(home)?;
+            match precheck {
                 record::Precheck::Append => {
-                    Ok(record::append_history(deps, cmd)?)
+                    if let Some(rule) = rules.check(&original, &entry, Some(&deps.index.try_lock().unwrap()))? {
+                        // the command itself stays out of the log, it may be ignored for holding a secret
+                        log::debug!("Ignored a command of {} characters, matched rule {}", original.chars().count(), rule);
+                        return Ok(());
+                    }
+                    Ok(record::append_history(deps, &entry)?)
                 }
                 record::Precheck::Skip => {
                     Ok(())
                 }
                 record::Precheck::Unset => {
-                    Ok(println!("release-hooks"))
+                    println!("release-hooks");
+                    Ok(())
                 }
             }
         }
-        "search" if flags.len() == 0 => {
-            Err(ScribeError{ text: format!("Search requires at least 1 argument") })
+        "db" => {
+            match flags.first().map(|f| f.as_str()) {
+                Some("migrate") => {
+                    let dry_run = flags.iter().any(|f| f == "--dry-run");
+                    let home = init::scribe_dir()?;
+                    let mut index = init::open_index(&home, crypto::load(&home)?.as_ref())?;
+                    println!("Current schema version: {}", migrate::current_version(&index)?);
+
+                    let migrations = if dry_run {
+                        migrate::pending(&index)?
+                    } else {
+                        migrate::migrate(&mut index)?
+                    };
+                    if migrations.is_empty() {
+                        println!("Schema is up to date");
+                    }
+                    for migration in migrations {
+                        if dry_run {
+                            println!("Would apply {:04} {}:\n{}", migration.version, migration.name, migration.sql.trim());
+                        } else {
+                            println!("Applied {:04} {}", migration.version, migration.name);
+                        }
+                    }
+                    Ok(())
+                }
+                _ => {
+                    Err(ScribeError{ text: format!("Unknown db subcommand {:?}, expected 'migrate [--dry-run]'", flags.first()) })
+                }
+            }
         }
-        "search" => {
+        "archive" => {
+            let home = init::scribe_dir()?;
+            match flags.first().map(|f| f.as_str()) {
+                Some("list") => {
+                    for (segment, valid) in archive::verify(&home)? {
+                        println!("{}\t{} entries\t{}\t{}", segment.name, segment.entries, segment.sha256,
+                            if valid { "ok" } else { "CHECKSUM MISMATCH" });
+                    }
+                    Ok(())
+                }
+                Some("compact") => {
+                    let mut compression = archive::Compression::None;
+                    let mut keep = 0;
+                    let mut args = flags[1..].iter();
+                    while let Some(arg) = args.next() {
+                        match (arg.as_str(), args.next()) {
+                            ("--compress", Some(value)) => {
+                                compression = archive::Compression::parse(value)?;
+                            }
+                            ("--keep", Some(value)) => {
+                                keep = value.parse().map_err(|_| ScribeError{ text: format!("Invalid value '{}' for --keep", value) })?;
+                            }
+                            (flag, _) => {
+                                return Err(ScribeError{ text: format!("Unknown or incomplete flag '{}' for archive compact", flag) });
+                            }
+                        }
+                    }
+
+                    let report = archive::compact(&home, compression, keep, crypto::load(&home)?.as_ref())?;
+                    match report.segment {
+                        Some(segment) => println!("Compacted {} segments into {} ({} entries)", report.merged.len(), segment.name, segment.entries),
+                        None => println!("Nothing to compact"),
+                    }
+                    Ok(())
+                }
+                _ => {
+                    Err(ScribeError{ text: format!("Unknown archive subcommand {:?}, expected 'list' or 'compact [--compress none|gzip|zstd] [--keep N]'", flags.first()) })
+                }
+            }
+        }
+        "export" => {
+            let (filters, rest) = export::Filters::parse(flags, record::now()?)?;
+            let format = match rest.as_slice() {
+                [] => export::Format::JsonLines,
+                [flag, name] if flag == "--format" => export::Format::parse(name)?,
+                _ => {
+                    return Err(ScribeError{
+                        text: format!("Usage: export [--format {}] [--since T] [--until T] [--host H] [--cwd DIR]", export::FORMATS.join("|")),
+                    });
+                }
+            };
+
+            let deps = init::deps(init::scribe_dir()?)?;
+            let stdout = std::io::stdout();
+            let mut writer = std::io::BufWriter::new(stdout.lock());
+            let count = export::export(deps, format, &filters, &mut writer)?;
+            log::info!("Exported {} entries", count);
+            Ok(())
+        }
+        "import" => {
+            let mut format = None;
+            let mut path = None;
+            let mut args = flags.iter();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--from" => format = args.next(),
+                    _ if path.is_none() => path = Some(std::path::PathBuf::from(arg)),
+                    _ => {
+                        return Err(ScribeError{ text: format!("Unexpected argument '{}' for import", arg) });
+                    }
+                }
+            }
+
+            let format = format.ok_or(ScribeError{
+                text: format!("Import requires --from <{}> [path]", import::FORMATS.join("|")),
+            })?;
+            let importer = import::importer(format)?;
+            let path = path.or_else(|| importer.default_path()).ok_or(ScribeError{
+                text: format!("Import from '{}' requires a path", format),
+            })?;
+
             let deps = init::deps(init::scribe_dir()?)?;
+            let summary = import::import(deps, importer.as_ref(), &path)?;
+            for malformed in summary.malformed.iter() {
+                eprintln!("{}:{}: {}", path.display(), malformed.line, malformed.reason);
+            }
+            println!("Imported {} entries, skipped {} duplicates and {} malformed lines",
+                summary.imported, summary.skipped, summary.malformed.len());
+            Ok(())
+        }
+        #[cfg(feature = "bench")]
+        "bench" => {
+            bench::bench(bench::Options::parse(flags)?)?;
+            Ok(())
+        }
+        "reindex" => {
+            let home = init::scribe_dir()?;
+            let report = reindex::reindex(&home, crypto::load(&home)?.as_ref())?;
+            for (path, malformed) in report.malformed.iter() {
+                eprintln!("{}:{}: {}", path.display(), malformed.line, malformed.reason);
+            }
+            println!("Indexed {} entries, skipped {} malformed lines", report.indexed, report.malformed.len());
+            Ok(())
+        }
+        "encrypt" => {
+            let passphrase = match flags {
+                [] => false,
+                [flag] if flag == "--passphrase" => true,
+                _ => {
+                    return Err(ScribeError{ text: "Usage: encrypt [--passphrase]".to_owned() });
+                }
+            };
+
+            let home = init::scribe_dir()?;
+            // an existing key means a previous run was interrupted, finish with the same key
+            let key = match crypto::load(&home)? {
+                Some(key) => key,
+                None if passphrase => crypto::create(&home, Some(&crypto::read_passphrase(true)?))?,
+                None => crypto::create(&home, None)?,
+            };
+
+            let entries = archive::reencode(&home, Some(&key))?;
+            let report = reindex::reindex(&home, Some(&key))?;
+            println!("Encrypted {} archived entries and {} indexed entries, the key is in {}",
+                entries, report.indexed, crypto::key_path(&home).display());
+            if passphrase {
+                println!("The history is unlocked until you log out, run 'scribe unlock' in each new login session");
+            }
+            Ok(())
+        }
+        "unlock" => {
+            if crypto::unlock_session(&init::scribe_dir()?)? {
+                println!("Unlocked the history until you log out or run 'scribe lock'");
+            } else {
+                println!("The history is not protected by a passphrase");
+            }
+            Ok(())
+        }
+        "lock" => {
+            if crypto::lock_session(&init::scribe_dir()?)? {
+                println!("Locked the history, run 'scribe unlock' to record and search again");
+            } else {
+                println!("The history was not unlocked");
+            }
+            Ok(())
+        }
+        "scan-secrets" => {
+            let action = match flags {
+                [] => None,
+                [flag] if flag == "--redact" => Some(scan::Action::Redact),
+                [flag] if flag == "--purge" => Some(scan::Action::Purge),
+                _ => {
+                    return Err(ScribeError{ text: "Usage: scan-secrets [--redact|--purge]".to_owned() });
+                }
+            };
 
-            let mut tty = termion::get_tty()?;
-            let mut reader = tty.try_clone()?;
-            let mut writer = tty.try_clone()?;
+            let home = init::scribe_dir()?;
+            let key = crypto::load(&home)?;
+            let redactor = record::Redactor::load(&home)?;
+            let suspects = scan::scan(&home, &init::open_index(&home, key.as_ref())?, key.as_ref(), &redactor)?;
+            for suspect in suspects.iter() {
+                let (command, _) = redactor.redact(&suspect.entry.command);
+                println!("{}\t{}\t{}\t{}{}",
+                    suspect.oid.map(|oid| oid.to_string()).unwrap_or_else(|| "-".to_owned()),
+                    dates::format_local(suspect.entry.timestamp),
+                    suspect.detectors.join(","),
+                    command,
+                    if suspect.files.is_empty() { "\t(index only)" } else { "" });
+            }
+
+            match action {
+                None if suspects.is_empty() => println!("No suspected secrets found"),
+                None => println!("Found {} entries with suspected secrets, run with --redact or --purge to remove them", suspects.len()),
+                Some(action) => {
+                    let changed = scan::clean(&home, key.as_ref(), &redactor, action)?;
+                    println!("{} {} archived entries and rebuilt the index",
+                        if action == scan::Action::Redact { "Redacted" } else { "Purged" }, changed);
+                }
+            }
+            Ok(())
+        }
+        "config" => {
+            let home = init::scribe_dir()?;
+            match flags {
+                [command] if command == "path" => {
+                    println!("{}", config::path(&home).display());
+                }
+                [command] if command == "list" => {
+                    if let Some(e) = &broken {
+                        eprintln!("Invalid configuration, showing the defaults: {}", e.cause);
+                    }
+                    for key in config::keys() {
+                        let from = if std::env::var(config::env_var(&key)).is_ok() { format!("\t# from {}", config::env_var(&key)) } else { String::new() };
+                        println!("{} = {}{}", key, config::lookup(&config, &key)?, from);
+                    }
+                }
+                [command, key] if command == "get" => {
+                    if let Some(e) = &broken {
+                        eprintln!("Invalid configuration, showing the default: {}", e.cause);
+                    }
+                    println!("{}", config::get(&config, key)?);
+                }
+                [command, key, value] if command == "set" => {
+                    config::set(&home, key, value)?;
+                }
+                [command] if command == "edit" => {
+                    config::edit(&home)?;
+                }
+                _ => {
+                    return Err(ScribeError{ text: "Usage: config path | list | get <key> | set <key> <value> | edit".to_owned() });
+                }
+            }
+            Ok(())
+        }
+        "search" if flags.is_empty() => {
+            Err(ScribeError{ text: "Search requires at least 1 argument".to_owned() })
+        }
+        "search" => {
+            let deps = init::deps(init::scribe_dir()?)?;
 
             // TODO separate subcommand
-            if flags.get(0).unwrap() == &String::from("--interactive") {
-                let response = search::interactive(deps, &mut tty, &mut reader, &mut writer)?;
+            let options = search::Options::parse(flags, &config)?;
+            if options.interactive {
+                let tty = termion::get_tty()?;
+                let mut reader = tty.try_clone()?;
+                let mut writer = tty.try_clone()?;
+                // fish and bash `bind -x` hand over the terminal in cooked mode, keys and the cursor
+                // position reply have to be read as they arrive. Restored when `raw` is dropped.
+                let mut raw = tty.into_raw_mode()?;
+
+                let response = search::interactive(deps, &options, &mut raw, &mut reader, &mut writer)?;
+                drop(raw);
                 if let Some(response) = response {
                     println!("{}", response);
                 }
             } else {
-                let matches = search::find_recent_matches(deps, flags.join(" ").to_string())?;
-                for m in matches.iter() {
-                    println!("{} {}", m.0, m.1);
-                }
+                search::print(deps, &options, &mut std::io::stdout().lock())?;
             }
             Ok(())
         }
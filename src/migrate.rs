@@ -0,0 +1,146 @@
+use std::convert::From;
+use std::fmt;
+
+use rusqlite::{self, Connection, OptionalExtension};
+
+/// Schema changes for `index.db`, applied in order and tracked with `PRAGMA user_version`.
+/// Migrations are append-only: never edit one that has been released, add a new one instead.
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+    /// Optional migrations depend on SQLite extensions that may be compiled out, when one
+    /// fails the version is still bumped and the features it backs stay disabled
+    pub optional: bool,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration{
+        version: 1,
+        name: "create_history",
+        sql: include_str!("etc/migrations/0001_create_history.sql"),
+        optional: false,
+    },
+    Migration{
+        version: 2,
+        name: "history_metadata",
+        sql: include_str!("etc/migrations/0002_history_metadata.sql"),
+        optional: false,
+    },
+    Migration{
+        version: 3,
+        name: "history_timestamp_index",
+        sql: include_str!("etc/migrations/0003_history_timestamp_index.sql"),
+        optional: false,
+    },
+    Migration{
+        version: 4,
+        name: "history_fts",
+        sql: include_str!("etc/migrations/0004_history_fts.sql"),
+        optional: true,
+    },
+    Migration{
+        version: 5,
+        name: "history_command_index",
+        sql: include_str!("etc/migrations/0005_history_command_index.sql"),
+        optional: false,
+    },
+];
+
+#[derive(Debug)]
+pub struct MigrateError {
+    pub cause: String,
+}
+
+impl fmt::Display for MigrateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.cause)
+    }
+}
+
+impl From<rusqlite::Error> for MigrateError {
+    fn from(err: rusqlite::Error) -> Self {
+        MigrateError {
+            cause: format!("Underlying IndexSQL (sqlite3) error occured: {:?}", err),
+        }
+    }
+}
+
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+fn user_version(index: &Connection) -> Result<u32, MigrateError> {
+    Ok(index.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+/// Indexes created before migrations existed have `user_version = 0` but already
+/// contain the `history` table, so their version is inferred from the columns present.
+fn legacy_version(index: &Connection) -> Result<u32, MigrateError> {
+    let table = index.query_row(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'history'",
+        [],
+        |row| row.get::<_, String>(0),
+    ).optional()?;
+    if table.is_none() {
+        return Ok(0);
+    }
+
+    let mut statement = index.prepare("PRAGMA table_info(history)")?;
+    let columns = statement
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<String>, _>>()?;
+
+    if columns.iter().any(|c| c == "exit_code") {
+        Ok(2)
+    } else {
+        Ok(1)
+    }
+}
+
+pub fn current_version(index: &Connection) -> Result<u32, MigrateError> {
+    match user_version(index)? {
+        0 => legacy_version(index),
+        version => Ok(version),
+    }
+}
+
+pub fn pending(index: &Connection) -> Result<Vec<&'static Migration>, MigrateError> {
+    let current = current_version(index)?;
+    if current > latest_version() {
+        return Err(MigrateError{
+            cause: format!("index.db is at schema version {} but this build only knows up to {}, upgrade scribe", current, latest_version()),
+        });
+    }
+    Ok(MIGRATIONS.iter().filter(|m| m.version > current).collect())
+}
+
+/// Applies every pending migration, each in its own transaction together with the version bump
+pub fn migrate(index: &mut Connection) -> Result<Vec<&'static Migration>, MigrateError> {
+    let current = current_version(index)?;
+    if current != user_version(index)? {
+        log::info!("Detected legacy index at schema version {}", current);
+        index.execute_batch(&format!("PRAGMA user_version = {}", current))?;
+    }
+
+    let migrations = pending(index)?;
+    for migration in migrations.iter() {
+        log::info!("Applying index migration {:04} {}", migration.version, migration.name);
+        let tx = index.transaction()?;
+        match tx.execute_batch(migration.sql) {
+            Ok(()) => {}
+            Err(e) if migration.optional => {
+                log::warn!("Skipping optional migration {:04} {}: {}", migration.version, migration.name, e);
+                tx.rollback()?;
+                index.execute_batch(&format!("PRAGMA user_version = {}", migration.version))?;
+                continue;
+            }
+            Err(e) => return Err(MigrateError{
+                cause: format!("Migration {:04} {} failed: {}", migration.version, migration.name, e),
+            }),
+        }
+        tx.execute_batch(&format!("PRAGMA user_version = {}", migration.version))?;
+        tx.commit()?;
+    }
+    Ok(migrations)
+}
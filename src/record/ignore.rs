@@ -0,0 +1,149 @@
+// Ignore rules in the spirit of HISTIGNORE and HISTCONTROL. They are read from `ignore` in
+// the scribe dir and from every `.scribeignore` between the working directory and the root,
+// one rule per line with blank lines and `#` comments skipped:
+//
+//   cd *                 glob matched against the whole command, `*` and `?` wildcards
+//   regex:^git push\b    regex searched for anywhere in the command
+//   cwd:~/secrets        never record in the directory or below it, relative to the file
+//   min-length:3         commands shorter than this many characters
+//   ignore-duplicates    the same command as the previous one in the session
+//
+// A `.scribeignore` holding `*` keeps its whole directory tree out of the history. Invalid
+// rules and unreadable files are logged and skipped, a broken `.scribeignore` in a cloned
+// repository must not stop recording everywhere below it.
+
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use rusqlite::{named_params, OptionalExtension};
+
+use super::super::history::Entry;
+use super::RecordError;
+
+pub const IGNORE_FILE: &str = ".scribeignore";
+
+enum Kind {
+    Pattern(Regex),
+    Directory(PathBuf),
+    MinLength(usize),
+    Duplicates,
+}
+
+pub struct Rule {
+    text: String,
+    /// file and line the rule was read from
+    source: String,
+    kind: Kind,
+}
+
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' ({})", self.text, self.source)
+    }
+}
+
+pub fn rules_path(home: &Path) -> PathBuf {
+    home.join("ignore")
+}
+
+fn glob(pattern: &str) -> Result<Regex, regex::Error> {
+    // `(?s)` lets wildcards span the lines of a multi-line command
+    let mut expression = String::from("(?s)^");
+    for c in pattern.chars() {
+        match c {
+            '*' => expression.push_str(".*"),
+            '?' => expression.push('.'),
+            c => expression.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    expression.push('$');
+    Regex::new(&expression)
+}
+
+fn directory(value: &str, base: &Path) -> Option<PathBuf> {
+    let path = match value.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => dirs::home_dir()?.join(rest.trim_start_matches('/')),
+        _ => base.join(value),
+    };
+    Some(PathBuf::from(path.to_string_lossy().trim_end_matches('/')))
+}
+
+fn parse_rule(text: &str, base: &Path) -> Result<Kind, String> {
+    if text == "ignore-duplicates" {
+        return Ok(Kind::Duplicates);
+    }
+    match text.split_once(':') {
+        Some(("regex", pattern)) => Regex::new(pattern).map(Kind::Pattern).map_err(|e| e.to_string()),
+        Some(("cwd", dir)) => directory(dir, base).map(Kind::Directory).ok_or_else(|| "unable to detect the home dir".to_owned()),
+        Some(("min-length", length)) => length.trim().parse().map(Kind::MinLength)
+            .map_err(|_| format!("expected a length but found '{}'", length)),
+        _ => glob(text).map(Kind::Pattern).map_err(|e| e.to_string()),
+    }
+}
+
+fn read_rules(path: &Path, rules: &mut Vec<Rule>) -> Result<(), std::io::Error> {
+    let base = path.parent().unwrap_or_else(|| Path::new("/"));
+    for (n, line) in BufReader::new(std::fs::File::open(path)?).lines().enumerate() {
+        let line = line?;
+        let text = line.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let source = format!("{}:{}", path.display(), n + 1);
+        match parse_rule(text, base) {
+            Ok(kind) => rules.push(Rule{ text: text.to_owned(), source, kind }),
+            Err(reason) => log::warn!("Skipping invalid ignore rule '{}' at {}: {}", text, source, reason),
+        }
+    }
+    Ok(())
+}
+
+/// The last command recorded in `session`, or anywhere when the session is not known
+fn previous_command(index: &rusqlite::Connection, session: Option<&str>) -> Result<Option<String>, rusqlite::Error> {
+    index.query_row(
+        "SELECT command FROM history WHERE :session IS NULL OR session = :session ORDER BY oid DESC LIMIT 1",
+        named_params!{ ":session": session },
+        |row| row.get(0),
+    ).optional()
+}
+
+impl IgnoreRules {
+    /// Rules from the scribe dir at `home` and every `.scribeignore` from `cwd` up to the root
+    pub fn load(home: &Path, cwd: Option<&str>) -> Self {
+        let mut rules = vec![];
+        let files = std::iter::once(rules_path(home))
+            .chain(cwd.map(Path::new).into_iter().flat_map(Path::ancestors).map(|dir| dir.join(IGNORE_FILE)));
+        for path in files {
+            if path.is_file() {
+                if let Err(e) = read_rules(&path, &mut rules) {
+                    log::warn!("Skipping ignore rules in {}: {}", path.display(), e);
+                }
+            }
+        }
+        IgnoreRules{ rules }
+    }
+
+    /// The first rule ignoring `entry`. Patterns see the `command` as typed while duplicates
+    /// compare the command as it would be stored, after redaction, and never match without an index.
+    pub fn check(&self, command: &str, entry: &Entry, index: Option<&rusqlite::Connection>) -> Result<Option<&Rule>, RecordError> {
+        for rule in self.rules.iter() {
+            let matched = match (&rule.kind, index) {
+                (Kind::Pattern(pattern), _) => pattern.is_match(command),
+                (Kind::Directory(dir), _) => entry.cwd.as_deref().map(Path::new).map(|cwd| cwd.starts_with(dir)).unwrap_or(false),
+                (Kind::MinLength(length), _) => command.chars().count() < *length,
+                (Kind::Duplicates, Some(index)) => previous_command(index, entry.session.as_deref())?.as_deref() == Some(entry.command.as_str()),
+                (Kind::Duplicates, None) => false,
+            };
+            if matched {
+                return Ok(Some(rule));
+            }
+        }
+        Ok(None)
+    }
+}
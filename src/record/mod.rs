@@ -0,0 +1,195 @@
+use std::convert::From;
+use std::ffi::CStr;
+
+use super::archive;
+use super::history::{self, Entry};
+use super::init;
+
+use rusqlite::named_params;
+
+mod ignore;
+mod redact;
+
+pub use ignore::IgnoreRules;
+pub use redact::Redactor;
+
+pub struct RecordError {
+    pub cause: String
+}
+
+impl From<rusqlite::Error> for RecordError {
+    fn from(err: rusqlite::Error) -> Self {
+        RecordError {
+            cause: format!("Underlying IndexSQL (sqlite3) error occured: {:?}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for RecordError {
+    fn from(err: std::io::Error) -> Self {
+        RecordError {
+            cause: format!("Underlying IndexSQL error occured: {:?}", err),
+        }
+    }
+}
+
+impl From<archive::ArchiveError> for RecordError {
+    fn from(err: archive::ArchiveError) -> Self {
+        RecordError {
+            cause: format!("Unable to append to the archive: {}", err.cause),
+        }
+    }
+}
+
+impl From<std::time::SystemTimeError> for RecordError {
+    fn from(err: std::time::SystemTimeError) -> Self {
+        RecordError {
+            cause: format!("SystemTime error: {}", err)
+        }
+    }
+}
+
+pub enum Precheck {
+    Append,
+    Skip,
+    Unset,
+}
+
+pub fn precheck(cmd: String) -> Precheck {
+    match cmd.get(0..1) {
+        Some(c) => {
+            if c.chars().any(|c| c.is_whitespace()) {
+                Precheck::Skip
+            } else if cmd.eq("unset HISTFILE") {
+                Precheck::Unset
+            } else {
+                Precheck::Append
+            }
+        }
+        None => {
+            Precheck::Skip
+        }
+    }
+}
+
+pub fn now() -> Result<u32, RecordError> {
+    Ok(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs() as u32)
+}
+
+/// Identifier handed to each shell on startup so entries can be grouped per terminal
+pub fn new_session_id() -> Result<String, RecordError> {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+    Ok(format!("{:x}{:05x}", now.as_secs(), (now.subsec_nanos() ^ std::process::id()) & 0xfffff))
+}
+
+pub fn hostname() -> Option<String> {
+    let mut buf = [0 as libc::c_char; 256];
+    let result = unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) };
+    if result != 0 {
+        log::warn!("Unable to detect hostname: {}", std::io::Error::last_os_error());
+        return None;
+    }
+    buf[buf.len() - 1] = 0;
+    let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+fn username() -> Option<String> {
+    std::env::var("USER").or_else(|_| std::env::var("LOGNAME")).ok()
+}
+
+fn flag_value<'a>(flag: &str, value: Option<&'a String>) -> Result<&'a String, RecordError> {
+    value.ok_or(RecordError{ cause: format!("Missing value for '{}'", flag) })
+}
+
+fn flag_number<T: std::str::FromStr>(flag: &str, value: Option<&String>) -> Result<T, RecordError> {
+    let value = flag_value(flag, value)?;
+    value.parse().map_err(|_| RecordError{ cause: format!("Invalid value '{}' for '{}'", value, flag) })
+}
+
+pub struct Options {
+    pub entry: Entry,
+    /// print what would be recorded and redacted without writing anything
+    pub dry_run: bool,
+}
+
+/// Builds an entry from `record` arguments of the form
+/// `[--exit N] [--start T] [--end T] [--cwd DIR] [--session ID] [--dry-run] [--] command...`
+///
+/// Arguments without a leading flag are treated as the command, which keeps
+/// the original `scribe record "$cmd"` invocation working.
+pub fn parse_options(flags: &[String]) -> Result<Options, RecordError> {
+    let mut entry = Entry::new(String::new(), now()?);
+    let mut dry_run = false;
+    entry.hostname = hostname();
+    entry.user = username();
+
+    let mut args = flags.iter();
+    let mut command = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--exit" => entry.exit_code = Some(flag_number(arg, args.next())?),
+            "--start" => entry.timestamp = flag_number(arg, args.next())?,
+            "--end" => entry.end_timestamp = Some(flag_number(arg, args.next())?),
+            "--cwd" => entry.cwd = Some(flag_value(arg, args.next())?.clone()),
+            "--session" => entry.session = Some(flag_value(arg, args.next())?.clone()),
+            "--dry-run" => dry_run = true,
+            "--" => {
+                command.extend(args.by_ref().cloned());
+            }
+            _ => {
+                command.push(arg.clone());
+                command.extend(args.by_ref().cloned());
+            }
+        }
+    }
+
+    if entry.cwd.is_none() {
+        entry.cwd = std::env::current_dir().ok().map(|dir| dir.to_string_lossy().into_owned());
+    }
+    entry.command = command.join(" ");
+    Ok(Options{ entry, dry_run })
+}
+
+pub fn append_history(deps: init::DataStores, entry: &Entry) -> Result<(), RecordError> {
+    let line = history::encode_line(entry, deps.key.as_ref());
+    archive::append(&deps.home, &deps.archive, entry.timestamp, &line, deps.key.as_ref())?;
+    index_entry(&deps.index.try_lock().unwrap(), entry)?;
+
+    Ok(())
+}
+
+/// Adds an entry to the index only, the archive is expected to already contain it
+pub fn index_entry(index: &rusqlite::Connection, entry: &Entry) -> Result<(), rusqlite::Error> {
+    index.execute(r#"
+        INSERT INTO history(command, timestamp, end_timestamp, exit_code, cwd, hostname, user, session)
+        VALUES (:command, :timestamp, :end_timestamp, :exit_code, :cwd, :hostname, :user, :session)
+    "#, named_params!{
+        ":command": entry.command,
+        ":timestamp": entry.timestamp,
+        ":end_timestamp": entry.end_timestamp,
+        ":exit_code": entry.exit_code,
+        ":cwd": entry.cwd,
+        ":hostname": entry.hostname,
+        ":user": entry.user,
+        ":session": entry.session,
+    })?;
+
+    Ok(())
+}
+/// Columns read back by `row_to_entry`, in order
+pub const ENTRY_COLUMNS: &str = "command, timestamp, end_timestamp, exit_code, cwd, hostname, user, session";
+
+/// Reads an entry selected with `ENTRY_COLUMNS` starting at column `offset`
+pub fn row_to_entry(row: &rusqlite::Row, offset: usize) -> Result<Entry, rusqlite::Error> {
+    Ok(Entry{
+        command: row.get(offset)?,
+        timestamp: row.get(offset + 1)?,
+        end_timestamp: row.get(offset + 2)?,
+        exit_code: row.get(offset + 3)?,
+        cwd: row.get(offset + 4)?,
+        hostname: row.get(offset + 5)?,
+        user: row.get(offset + 6)?,
+        session: row.get(offset + 7)?,
+    })
+}
@@ -0,0 +1,81 @@
+use std::convert::From;
+use std::path::{Path, PathBuf};
+
+use super::archive;
+use super::crypto::{self, Key};
+use super::history;
+use super::migrate;
+use super::record;
+
+pub struct ReindexError {
+    pub cause: String,
+}
+
+impl From<std::io::Error> for ReindexError {
+    fn from(err: std::io::Error) -> Self {
+        ReindexError{ cause: format!("IO Error: {}", err) }
+    }
+}
+
+impl From<rusqlite::Error> for ReindexError {
+    fn from(err: rusqlite::Error) -> Self {
+        ReindexError{ cause: format!("Underlying IndexSQL (sqlite3) error occured: {:?}", err) }
+    }
+}
+
+impl From<archive::ArchiveError> for ReindexError {
+    fn from(err: archive::ArchiveError) -> Self {
+        ReindexError{ cause: err.cause }
+    }
+}
+
+impl From<crypto::CryptoError> for ReindexError {
+    fn from(err: crypto::CryptoError) -> Self {
+        ReindexError{ cause: err.cause }
+    }
+}
+
+impl From<migrate::MigrateError> for ReindexError {
+    fn from(err: migrate::MigrateError) -> Self {
+        ReindexError{ cause: err.cause }
+    }
+}
+
+pub struct Report {
+    pub indexed: usize,
+    pub malformed: Vec<(PathBuf, history::Malformed)>,
+}
+
+/// Rebuilds `data/index.db` from the archive. The new index is built next to the
+/// old one and renamed over it once complete, so a failure leaves the old index untouched.
+/// With a key the new index is encrypted, whether or not the old one was.
+pub fn reindex(home: &Path, key: Option<&Key>) -> Result<Report, ReindexError> {
+    let data = home.join("data");
+    let target = data.join("index.db");
+    let staging = data.join("index.db.reindex");
+    if staging.exists() {
+        std::fs::remove_file(&staging)?;
+    }
+
+    let mut report = Report{ indexed: 0, malformed: vec![] };
+    {
+        let mut index = rusqlite::Connection::open(&staging)?;
+        crypto::unlock(&index, key)?;
+        migrate::migrate(&mut index)?;
+
+        let tx = index.transaction()?;
+        for path in archive::archive_files(home)? {
+            let archive = archive::read_file(&path, key)?;
+            for entry in archive.entries.iter() {
+                record::index_entry(&tx, entry)?;
+            }
+            report.indexed += archive.entries.len();
+            report.malformed.extend(archive.malformed.into_iter().map(|m| (path.clone(), m)));
+        }
+        tx.commit()?;
+    }
+
+    std::fs::rename(&staging, &target)?;
+    log::info!("Reindexed {} entries with {} malformed lines", report.indexed, report.malformed.len());
+    Ok(report)
+}
@@ -0,0 +1,121 @@
+// `scan-secrets` runs the redaction detectors over history recorded before they existed,
+// or before a rule was added. Both stores are scanned since they can disagree after an
+// interrupted import or a lost index. Cleaning rewrites the archive and then rebuilds the
+// index from it, so every store is replaced by a new file instead of edited in place.
+
+use std::collections::HashMap;
+use std::convert::From;
+use std::path::Path;
+
+use super::archive;
+use super::crypto::Key;
+use super::history::Entry;
+use super::record::{self, Redactor};
+use super::reindex;
+
+pub struct ScanError {
+    pub cause: String,
+}
+
+impl From<std::io::Error> for ScanError {
+    fn from(err: std::io::Error) -> Self {
+        ScanError{ cause: format!("IO Error: {}", err) }
+    }
+}
+
+impl From<rusqlite::Error> for ScanError {
+    fn from(err: rusqlite::Error) -> Self {
+        ScanError{ cause: format!("Underlying IndexSQL (sqlite3) error occured: {:?}", err) }
+    }
+}
+
+impl From<archive::ArchiveError> for ScanError {
+    fn from(err: archive::ArchiveError) -> Self {
+        ScanError{ cause: err.cause }
+    }
+}
+
+impl From<reindex::ReindexError> for ScanError {
+    fn from(err: reindex::ReindexError) -> Self {
+        ScanError{ cause: err.cause }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Action {
+    /// replace each secret with the placeholder and keep the rest of the command
+    Redact,
+    /// drop every entry holding a secret
+    Purge,
+}
+
+/// An entry with at least one suspected secret
+pub struct Suspect {
+    /// None when the entry is only in the archive
+    pub oid: Option<u32>,
+    pub entry: Entry,
+    pub detectors: Vec<String>,
+    /// archive files holding the entry, empty when it is only in the index
+    pub files: Vec<String>,
+}
+
+fn detectors(redactor: &Redactor, command: &str) -> Vec<String> {
+    let mut names: Vec<String> = redactor.find(command).into_iter().map(|f| f.detector).collect();
+    names.dedup();
+    names
+}
+
+/// Every indexed row and archived line with a suspected secret, index rows first by oid.
+/// Archived lines are matched to index rows by timestamp and command.
+pub fn scan(home: &Path, index: &rusqlite::Connection, key: Option<&Key>, redactor: &Redactor) -> Result<Vec<Suspect>, ScanError> {
+    let mut suspects = vec![];
+    let mut indexed = HashMap::new();
+
+    let mut statement = index.prepare(&format!("SELECT oid, {} FROM history ORDER BY oid", record::ENTRY_COLUMNS))?;
+    let rows = statement.query_map([], |row| Ok((row.get::<_, u32>(0)?, record::row_to_entry(row, 1)?)))?;
+    for row in rows {
+        let (oid, entry) = row?;
+        let detectors = detectors(redactor, &entry.command);
+        if !detectors.is_empty() {
+            indexed.insert((entry.timestamp, entry.command.clone()), suspects.len());
+            suspects.push(Suspect{ oid: Some(oid), entry, detectors, files: vec![] });
+        }
+    }
+
+    for path in archive::archive_files(home)? {
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        for entry in archive::read_file(&path, key)?.entries {
+            let detectors = detectors(redactor, &entry.command);
+            if detectors.is_empty() {
+                continue;
+            }
+            match indexed.get(&(entry.timestamp, entry.command.clone())) {
+                Some(&n) => suspects[n].files.push(name.clone()),
+                None => suspects.push(Suspect{ oid: None, entry, detectors, files: vec![name.clone()] }),
+            }
+        }
+    }
+    Ok(suspects)
+}
+
+/// Redacts or purges every archived entry with a suspected secret and rebuilds the index from
+/// the archive, which also drops secrets only the index held. Returns how many archived entries
+/// were changed. Oids change when entries are purged.
+pub fn clean(home: &Path, key: Option<&Key>, redactor: &Redactor, action: Action) -> Result<usize, ScanError> {
+    let mut changed = 0;
+    archive::rewrite(home, key, &mut |mut entry| {
+        let findings = redactor.apply(&mut entry);
+        if findings.is_empty() {
+            return Some(entry);
+        }
+        changed += 1;
+        match action {
+            Action::Redact => Some(entry),
+            Action::Purge => None,
+        }
+    })?;
+
+    let report = reindex::reindex(home, key)?;
+    log::info!("Cleaned {} archived entries with secrets and reindexed {} entries", changed, report.indexed);
+    Ok(changed)
+}
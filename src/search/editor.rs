@@ -0,0 +1,123 @@
+// Emacs style line editing for the search prompt. The cursor is a byte offset into the
+// query that always sits on a grapheme boundary, and widths are display columns so wide
+// and combining characters keep the terminal cursor where the text actually is.
+
+use termion::event::Key;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+pub struct Editor {
+    text: String,
+    cursor: usize,
+    /// most recently killed text, inserted again by Ctrl-Y
+    killed: String,
+}
+
+fn is_word(grapheme: &str) -> bool {
+    grapheme.chars().any(|c| c.is_alphanumeric())
+}
+
+fn is_not_space(grapheme: &str) -> bool {
+    !grapheme.chars().all(char::is_whitespace)
+}
+
+impl Editor {
+    pub fn new(text: &str) -> Self {
+        Editor{ text: text.to_owned(), cursor: text.len(), killed: String::new() }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Display columns between the start of the query and the cursor
+    pub fn cursor_width(&self) -> usize {
+        self.text[..self.cursor].width()
+    }
+
+    fn previous_grapheme(&self) -> usize {
+        self.text[..self.cursor].grapheme_indices(true).next_back().map(|(i, _)| i).unwrap_or(0)
+    }
+
+    fn next_grapheme(&self) -> usize {
+        self.text[self.cursor..].graphemes(true).next().map(|g| self.cursor + g.len()).unwrap_or(self.cursor)
+    }
+
+    /// Start of the word before the cursor, skipping any separators directly in front of it
+    fn word_start(&self, word: fn(&str) -> bool) -> usize {
+        let mut start = self.cursor;
+        let mut seen = false;
+        for (i, grapheme) in self.text[..self.cursor].grapheme_indices(true).rev() {
+            if word(grapheme) {
+                seen = true;
+            } else if seen {
+                break;
+            }
+            start = i;
+        }
+        start
+    }
+
+    /// End of the word after the cursor, skipping any separators directly after it
+    fn word_end(&self, word: fn(&str) -> bool) -> usize {
+        let mut seen = false;
+        for (i, grapheme) in self.text[self.cursor..].grapheme_indices(true) {
+            if word(grapheme) {
+                seen = true;
+            } else if seen {
+                return self.cursor + i;
+            }
+        }
+        self.text.len()
+    }
+
+    /// Unlike `kill` this leaves the text available to yank alone, used for single graphemes
+    fn delete(&mut self, start: usize, end: usize) {
+        self.text.replace_range(start..end, "");
+        self.cursor = start;
+    }
+
+    fn kill(&mut self, start: usize, end: usize) {
+        if start == end {
+            return;
+        }
+        self.killed = self.text[start..end].to_owned();
+        self.text.replace_range(start..end, "");
+        self.cursor = start;
+    }
+
+    fn insert(&mut self, text: &str) {
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    /// Applies an editing key, returning false for keys that are not editing keys
+    pub fn handle(&mut self, key: &Key) -> bool {
+        match key {
+            Key::Char('\n') | Key::Char('\t') => return false,
+            Key::Char(c) => {
+                let mut buffer = [0; 4];
+                self.insert(c.encode_utf8(&mut buffer));
+            }
+            Key::Left | Key::Ctrl('b') => self.cursor = self.previous_grapheme(),
+            Key::Right | Key::Ctrl('f') => self.cursor = self.next_grapheme(),
+            Key::Home | Key::Ctrl('a') => self.cursor = 0,
+            Key::End | Key::Ctrl('e') => self.cursor = self.text.len(),
+            Key::Alt('b') => self.cursor = self.word_start(is_word),
+            Key::Alt('f') => self.cursor = self.word_end(is_word),
+            Key::Backspace | Key::Ctrl('h') => self.delete(self.previous_grapheme(), self.cursor),
+            Key::Delete => self.delete(self.cursor, self.next_grapheme()),
+            Key::Ctrl('w') => self.kill(self.word_start(is_not_space), self.cursor),
+            Key::Alt('\x7f') | Key::Alt('\x08') => self.kill(self.word_start(is_word), self.cursor),
+            Key::Alt('d') => self.kill(self.cursor, self.word_end(is_word)),
+            Key::Ctrl('k') => self.kill(self.cursor, self.text.len()),
+            Key::Ctrl('u') => self.kill(0, self.cursor),
+            Key::Ctrl('y') => {
+                let killed = self.killed.clone();
+                self.insert(&killed);
+            }
+            _ => return false,
+        }
+        true
+    }
+}
@@ -0,0 +1,107 @@
+// fzf-style subsequence scoring. Every query char must appear in order in the text;
+// the best alignment is found with a small dynamic program that rewards matches on
+// word boundaries and runs of consecutive chars, and penalizes the gaps between them.
+
+const MATCH: i64 = 16;
+const CONSECUTIVE: i64 = 12;
+const BOUNDARY: i64 = 10;
+const FIRST_CHAR_MULTIPLIER: i64 = 2;
+const GAP_START: i64 = 3;
+const GAP_EXTENSION: i64 = 1;
+const NONE: i64 = i64::MIN / 2;
+
+#[derive(Clone, Debug)]
+pub struct FuzzyMatch {
+    pub score: i64,
+    /// char indices into the text of every matched query char
+    pub positions: Vec<usize>,
+}
+
+/// Case-insensitive unless the query contains an uppercase char, like fzf and vim
+pub fn case_sensitive(query: &str) -> bool {
+    query.chars().any(|c| c.is_uppercase())
+}
+
+fn fold(c: char, sensitive: bool) -> char {
+    if sensitive {
+        c
+    } else {
+        c.to_lowercase().next().unwrap_or(c)
+    }
+}
+
+/// `text` folded the way case-insensitive queries compare it, for the SQL prefilter
+pub fn fold_case(text: &str) -> String {
+    text.chars().map(|c| fold(c, false)).collect()
+}
+
+fn boundary_bonus(prev: Option<char>, current: char) -> i64 {
+    match prev {
+        None => BOUNDARY,
+        Some(p) if p.is_whitespace() => BOUNDARY,
+        Some(p) if "/-_.,:;|&=@'\"()[]{}".contains(p) => BOUNDARY - 2,
+        Some(p) if p.is_lowercase() && current.is_uppercase() => BOUNDARY - 3,
+        _ => 0,
+    }
+}
+
+pub fn score(query: &str, text: &str) -> Option<FuzzyMatch> {
+    let sensitive = case_sensitive(query);
+    let query: Vec<char> = query.chars().map(|c| fold(c, sensitive)).collect();
+    let original: Vec<char> = text.chars().collect();
+    let text: Vec<char> = original.iter().map(|&c| fold(c, sensitive)).collect();
+    if query.is_empty() || query.len() > text.len() {
+        return None;
+    }
+
+    let bonus: Vec<i64> = (0..original.len())
+        .map(|j| boundary_bonus(if j == 0 { None } else { Some(original[j - 1]) }, original[j]))
+        .collect();
+
+    // scores[i][j]: best score with query[i] matched at text[j], from[i][j]: where query[i - 1] matched
+    let mut scores = vec![vec![NONE; text.len()]; query.len()];
+    let mut from = vec![vec![0usize; text.len()]; query.len()];
+
+    for (j, &c) in text.iter().enumerate() {
+        if c == query[0] {
+            scores[0][j] = MATCH + bonus[j] * FIRST_CHAR_MULTIPLIER;
+        }
+    }
+
+    for i in 1..query.len() {
+        // best predecessor at least one char back, already charged for the gap up to j
+        let mut gap_best = NONE;
+        let mut gap_from = 0;
+        for j in i..text.len() {
+            if gap_best > NONE {
+                gap_best -= GAP_EXTENSION;
+            }
+            if j >= 2 && scores[i - 1][j - 2] > NONE && scores[i - 1][j - 2] - GAP_START > gap_best {
+                gap_best = scores[i - 1][j - 2] - GAP_START;
+                gap_from = j - 2;
+            }
+
+            if text[j] != query[i] {
+                continue;
+            }
+            let consecutive = if scores[i - 1][j - 1] > NONE { scores[i - 1][j - 1] + CONSECUTIVE } else { NONE };
+            let (best, prev) = if consecutive >= gap_best { (consecutive, j - 1) } else { (gap_best, gap_from) };
+            if best > NONE {
+                scores[i][j] = best + MATCH + bonus[j];
+                from[i][j] = prev;
+            }
+        }
+    }
+
+    let last = query.len() - 1;
+    let (end, &best) = scores[last].iter().enumerate().max_by_key(|(j, s)| (**s, std::cmp::Reverse(*j)))?;
+    if best <= NONE {
+        return None;
+    }
+
+    let mut positions = vec![end; query.len()];
+    for i in (1..query.len()).rev() {
+        positions[i - 1] = from[i][positions[i]];
+    }
+    Some(FuzzyMatch{ score: best, positions })
+}
@@ -0,0 +1,220 @@
+// Incremental search for the inline widget, following zsh's history-incremental-search:
+// refining the query resumes from the match on screen, Ctrl-R and Ctrl-S step to the next
+// distinct older or newer match, and running off either end of history marks the search
+// as failing while keeping the last match visible. Stepping again while failing wraps.
+//
+// With global dedup stepping older never shows a command twice for the same query, and
+// stepping newer retraces the matches already shown rather than searching again. Fuzzy
+// and frecency results are not ordered by oid, stepping walks their ranked list instead.
+
+use std::collections::HashSet;
+
+use super::super::init::DataStores;
+use super::{find_frecent_matches, find_fuzzy_matches, find_next_match, highlight, Cursor, Dedup, Direction, Filter, Match, Mode, Rank, SearchError, FUZZY_CANDIDATES};
+
+pub struct Incremental {
+    pub current: Option<Match>,
+    pub failing: bool,
+    dedup: Dedup,
+    query: String,
+    mode: Option<Mode>,
+    filter: Filter,
+    ranking: Rank,
+    /// directory frecency favours
+    here: Option<String>,
+    /// fuzzy and frecent matches are ranked by score rather than oid, so they are computed once per query
+    ranked: Vec<Match>,
+    rank: usize,
+    /// commands shown since the query last changed
+    seen: HashSet<String>,
+    /// matches stepped past with global dedup and their fuzzy rank, newest last
+    trail: Vec<(Match, usize)>,
+}
+
+/// Finds the first match at or beyond `from` in `direction` whose command is not skipped
+fn seek(deps: &DataStores, query: &str, mode: Mode, filter: &Filter, direction: Direction, from: u32, skip: &dyn Fn(&str) -> bool) -> Result<Option<Match>, SearchError> {
+    let mut cursor = Cursor{ direction, navigated: true, oid: from, rank: 0 };
+    loop {
+        let (found, next) = find_next_match(deps.clone(), query.to_owned(), mode, filter, cursor)?;
+        match found {
+            Some(found) if skip(&found.command) => {
+                let oid = match direction {
+                    Direction::Older => next.oid.checked_sub(1),
+                    Direction::Newer => next.oid.checked_add(1),
+                };
+                match oid {
+                    Some(oid) => cursor.oid = oid,
+                    None => return Ok(None),
+                }
+            }
+            found => return Ok(found),
+        }
+    }
+}
+
+impl Incremental {
+    pub fn new(dedup: Dedup, here: Option<String>) -> Self {
+        Incremental{
+            current: None,
+            failing: false,
+            dedup,
+            query: String::new(),
+            mode: None,
+            filter: Filter::default(),
+            ranking: Rank::Recency,
+            here,
+            ranked: vec![],
+            rank: 0,
+            seen: HashSet::new(),
+            trail: vec![],
+        }
+    }
+
+    /// Whether `command` should be stepped over, `current` is the match being stepped away from
+    fn skipped(&self, current: Option<&str>, command: &str) -> bool {
+        match self.dedup {
+            Dedup::Off => false,
+            Dedup::Consecutive => current == Some(command),
+            Dedup::Global => self.seen.contains(command),
+        }
+    }
+
+    fn show(&mut self, found: Option<Match>) {
+        match found {
+            Some(found) => {
+                self.seen.insert(found.command.clone());
+                self.current = Some(found);
+                self.failing = false;
+            }
+            None => {
+                self.failing = true;
+                // zsh keeps the last match on screen, only the highlight reflects the new query
+                if let Some(current) = self.current.as_mut() {
+                    current.positions = highlight(self.mode.unwrap_or(Mode::Substring), &self.query, &current.command);
+                }
+            }
+        }
+    }
+
+    fn ranks(&self, mode: Mode) -> bool {
+        mode == Mode::Fuzzy || self.ranking == Rank::Frecency
+    }
+
+    /// Re-runs the search after the query, mode, filter or ranking changed, resuming from the current match
+    pub fn update(&mut self, deps: &DataStores, query: &str, mode: Mode, filter: &Filter, ranking: Rank) -> Result<(), SearchError> {
+        let mode = mode.for_query(query);
+        let unchanged = self.mode == Some(mode) && &self.filter == filter && self.ranking == ranking;
+        if self.query == query && unchanged {
+            return Ok(());
+        }
+        self.query = query.to_owned();
+        self.mode = Some(mode);
+        self.filter = filter.clone();
+        self.ranking = ranking;
+        self.seen.clear();
+        self.trail.clear();
+
+        if query.is_empty() && filter.is_empty() {
+            self.current = None;
+            self.failing = false;
+            return Ok(());
+        }
+
+        if self.ranks(mode) {
+            self.ranked = match ranking {
+                Rank::Recency => find_fuzzy_matches(deps.clone(), query, filter, usize::MAX)?,
+                Rank::Frecency => find_frecent_matches(deps, query, mode, filter, self.here.as_deref(), 0, FUZZY_CANDIDATES as usize)?,
+            };
+            self.rank = 0;
+            let found = self.ranked.first().cloned();
+            self.show(found);
+            return Ok(());
+        }
+
+        let from = match (&self.current, unchanged) {
+            (Some(current), true) => current.oid,
+            _ => u32::MAX,
+        };
+        let found = seek(deps, query, mode, filter, Direction::Older, from, &|_| false)?;
+        self.show(found);
+        Ok(())
+    }
+
+    /// Moves to the next match in `direction` that dedup does not hide, wrapping around when already failing
+    pub fn step(&mut self, deps: &DataStores, direction: Direction) -> Result<(), SearchError> {
+        let mode = match self.mode {
+            Some(mode) if !self.query.is_empty() || !self.filter.is_empty() => mode,
+            _ => return Ok(()),
+        };
+        if self.dedup == Dedup::Global && matches!(direction, Direction::Newer) {
+            if let Some((found, rank)) = self.trail.pop() {
+                self.rank = rank;
+                self.show(Some(found));
+                return Ok(());
+            }
+        }
+        let previous = self.current.clone().map(|current| (current, self.rank));
+        let current = previous.as_ref().map(|(m, _)| m.command.clone());
+
+        let found = if self.ranks(mode) {
+            self.step_ranked(direction, current.as_deref())
+        } else {
+            self.step_history(deps, mode, direction, current.as_deref())?
+        };
+        let stepped_older = found.is_some() && matches!(direction, Direction::Older);
+        if let Some(previous) = previous.filter(|_| stepped_older && self.dedup == Dedup::Global) {
+            self.trail.push(previous);
+        }
+        self.show(found);
+        Ok(())
+    }
+
+    fn wrap(&mut self) {
+        self.seen.clear();
+        self.trail.clear();
+    }
+
+    fn step_ranked(&mut self, direction: Direction, current: Option<&str>) -> Option<Match> {
+        let next = match direction {
+            Direction::Older => self.ranked.iter().enumerate().skip(self.rank + 1).find(|(_, m)| !self.skipped(current, &m.command)),
+            Direction::Newer => self.ranked.iter().enumerate().take(self.rank).rev().find(|(_, m)| !self.skipped(current, &m.command)),
+        }.map(|(rank, _)| rank);
+        let wrapped = match direction {
+            Direction::Older => 0,
+            Direction::Newer => self.ranked.len().saturating_sub(1),
+        };
+        let rank = match next {
+            Some(rank) => Some(rank),
+            None if self.failing => {
+                self.wrap();
+                Some(wrapped)
+            }
+            None => None,
+        };
+        if let Some(rank) = rank {
+            self.rank = rank;
+        }
+        rank.and_then(|rank| self.ranked.get(rank).cloned())
+    }
+
+    fn step_history(&mut self, deps: &DataStores, mode: Mode, direction: Direction, current: Option<&str>) -> Result<Option<Match>, SearchError> {
+        let from = self.current.as_ref().and_then(|current| match direction {
+            Direction::Older => current.oid.checked_sub(1),
+            Direction::Newer => current.oid.checked_add(1),
+        });
+        let found = match from {
+            Some(from) => seek(deps, &self.query, mode, &self.filter, direction, from, &|command| self.skipped(current, command))?,
+            None => None,
+        };
+        if found.is_some() || !self.failing {
+            return Ok(found);
+        }
+
+        self.wrap();
+        let end = match direction {
+            Direction::Older => u32::MAX,
+            Direction::Newer => 0,
+        };
+        seek(deps, &self.query, mode, &self.filter, direction, end, &|_| false)
+    }
+}
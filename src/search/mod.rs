@@ -0,0 +1,1116 @@
+use std::collections::HashSet;
+use std::convert::From;
+
+use libc::{c_ushort, ioctl, TIOCGWINSZ};
+use std::io::{Read, Write};
+use std::os::unix::io::{ AsRawFd };
+
+use termion::{clear, cursor, style};
+use termion::cursor::DetectCursorPos;
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::screen::AlternateScreen;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use rusqlite::{self, named_params, ToSql};
+use rusqlite::types::Value;
+use rusqlite::functions::FunctionFlags;
+
+use super::config::Config;
+use super::dates;
+use super::history::Entry;
+use super::init::DataStores;
+use super::record;
+use editor::Editor;
+pub use palette::Palette;
+pub use output::{print, Field, Format};
+pub use query::Query;
+pub use rank::{find_frecent_matches, Rank};
+pub use scope::{Context, Scope};
+
+mod editor;
+mod fuzzy;
+mod incremental;
+mod output;
+mod palette;
+mod query;
+mod rank;
+mod scope;
+mod screen;
+
+/// Most recent rows considered when ranking fuzzy matches
+const FUZZY_CANDIDATES: u32 = 10_000;
+/// Largest score boost given to the most recent fuzzy candidate
+const FUZZY_RECENCY: i64 = 10;
+/// Shortest query the trigram index can answer, shorter queries scan with LIKE
+const TRIGRAM_LENGTH: usize = 3;
+/// Width of the working directory column in list mode
+const CWD_WIDTH: usize = 24;
+/// FTS5 phrase matching `:query` as a literal substring through the trigram index
+const FTS_PHRASE: &str = r#"history_fts MATCH '"' || replace(:query, '"', '""') || '"'"#;
+
+#[repr(C)]
+struct TermSize {
+    ws_row: c_ushort,
+    ws_col: c_ushort,
+    _ws_xpixel: c_ushort,
+    _ws_ypixel: c_ushort,
+}
+
+struct Position {
+    x: u16,
+    y: u16,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Direction {
+    Older,
+    Newer,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Substring,
+    Prefix,
+    Fuzzy,
+    Regex,
+}
+
+impl Mode {
+    pub fn parse(name: &str) -> Result<Self, SearchError> {
+        match name {
+            "substring" => Ok(Mode::Substring),
+            "prefix" => Ok(Mode::Prefix),
+            "fuzzy" => Ok(Mode::Fuzzy),
+            "regex" => Ok(Mode::Regex),
+            _ => Err(SearchError{ cause: format!("Unknown search mode '{}', expected fuzzy, substring, prefix or regex", name) }),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Substring => "substring",
+            Mode::Prefix => "prefix",
+            Mode::Fuzzy => "fuzzy",
+            Mode::Regex => "regex",
+        }
+    }
+
+    /// Order the modes are cycled through from inside `interactive`
+    fn next(self) -> Self {
+        match self {
+            Mode::Substring => Mode::Fuzzy,
+            Mode::Fuzzy => Mode::Prefix,
+            Mode::Prefix => Mode::Regex,
+            Mode::Regex => Mode::Substring,
+        }
+    }
+
+    /// SQL condition selecting matching rows, fuzzy matches are ranked in Rust instead.
+    /// `%` and `_` in the query are escaped so they only match themselves.
+    fn condition(self) -> &'static str {
+        match self {
+            Mode::Substring | Mode::Fuzzy => r"command LIKE '%' || replace(replace(replace(:query, '\', '\\'), '%', '\%'), '_', '\_') || '%' ESCAPE '\'",
+            Mode::Prefix => r"command LIKE replace(replace(replace(:query, '\', '\\'), '%', '\%'), '_', '\_') || '%' ESCAPE '\'",
+            Mode::Regex => "command REGEXP :query",
+        }
+    }
+
+    /// With only filters and no text every command matches, which fuzzy scoring cannot express
+    fn for_query(self, query: &str) -> Self {
+        if query.is_empty() { Mode::Substring } else { self }
+    }
+
+    /// Whether the trigram index can narrow the rows matched for `query`
+    fn indexed(self, query: &str, fts: bool) -> bool {
+        fts && (self == Mode::Substring || self == Mode::Prefix) && query.chars().count() >= TRIGRAM_LENGTH
+    }
+}
+
+/// Which repeated commands are hidden while stepping through or listing matches
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Dedup {
+    Off,
+    /// only runs of the same command directly after each other
+    Consecutive,
+    /// every command already shown since the query last changed
+    Global,
+}
+
+impl Dedup {
+    pub fn parse(name: &str) -> Result<Self, SearchError> {
+        match name {
+            "off" => Ok(Dedup::Off),
+            "consecutive" => Ok(Dedup::Consecutive),
+            "global" => Ok(Dedup::Global),
+            _ => Err(SearchError{ cause: format!("Unknown dedup setting '{}', expected off, consecutive or global", name) }),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Dedup::Off => "off",
+            Dedup::Consecutive => "consecutive",
+            Dedup::Global => "global",
+        }
+    }
+}
+
+/// Conditions over `history` columns that narrow a search beyond the query text, each clause
+/// brings its own named parameters
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Filter {
+    clauses: Vec<String>,
+    params: Vec<(String, Value)>,
+}
+
+impl Filter {
+    pub fn push(&mut self, clause: &str, params: Vec<(&str, Value)>) {
+        self.clauses.push(clause.to_owned());
+        self.params.extend(params.into_iter().map(|(name, value)| (name.to_owned(), value)));
+    }
+
+    pub fn extend(&mut self, other: Filter) {
+        self.clauses.extend(other.clauses);
+        self.params.extend(other.params);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.clauses.is_empty()
+    }
+
+    /// Conditions to append to an existing WHERE clause over `history`
+    fn sql(&self) -> String {
+        self.clauses.iter().map(|clause| format!(" AND ({})", clause)).collect()
+    }
+
+    /// Parameters for a statement using `sql`, followed by the statement's own
+    fn bind<'a>(&'a self, named: &[(&'a str, &'a dyn ToSql)]) -> Vec<(&'a str, &'a dyn ToSql)> {
+        let mut params: Vec<(&str, &dyn ToSql)> = self.params.iter().map(|(name, value)| (name.as_str(), value as &dyn ToSql)).collect();
+        params.extend_from_slice(named);
+        params
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Cursor {
+    pub direction: Direction,
+    pub navigated: bool,
+    pub oid: u32,
+    /// position in the ranked results for modes that are not ordered by oid
+    pub rank: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Match {
+    pub oid: u32,
+    pub command: String,
+    /// char indices of `command` that matched the query
+    pub positions: Vec<usize>,
+}
+
+pub struct Options {
+    pub interactive: bool,
+    /// show a scrolling list of matches instead of the single inline match
+    pub list: bool,
+    /// rows shown in list mode, defaults to the terminal height
+    pub height: Option<u16>,
+    /// take over the alternate screen with a list, preview pane and status bar
+    pub fullscreen: bool,
+    pub dedup: Dedup,
+    pub mode: Mode,
+    pub rank: Rank,
+    pub scope: Scope,
+    /// the shell's session id, compared against recorded sessions by the session scope
+    pub session: Option<String>,
+    pub format: Format,
+    pub fields: Vec<Field>,
+    pub limit: usize,
+    /// write the best match first instead of last
+    pub reverse: bool,
+    pub query: String,
+    /// name shown at the start of the prompt
+    pub prompt_prefix: String,
+    /// shown before the inline match
+    pub search_prefix: String,
+    /// widest inline match before it is cut off
+    pub max_width: usize,
+    pub palette: Palette,
+}
+
+impl Options {
+    /// Flags override the defaults from `config`
+    pub fn parse(flags: &[String], config: &Config) -> Result<Self, SearchError> {
+        let search = &config.search;
+        let mut options = Options{
+            interactive: false, list: search.list, height: None, fullscreen: search.fullscreen,
+            dedup: search.dedup, mode: search.mode, rank: search.rank, scope: search.scope, session: None,
+            format: Format::Plain, fields: vec![Field::Oid, Field::Command], limit: search.limit.get(), reverse: false, query: String::new(),
+            prompt_prefix: search.prompt_prefix.clone(), search_prefix: search.search_prefix.clone(),
+            max_width: search.max_width.get(), palette: Palette::new(&config.colors),
+        };
+        let mut query = vec![];
+        let mut args = flags.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--interactive" => options.interactive = true,
+                "--list" => options.list = true,
+                "--fullscreen" => options.fullscreen = true,
+                "--dedup" => {
+                    let name = args.next().ok_or(SearchError{ cause: "Missing value for '--dedup'".to_owned() })?;
+                    options.dedup = Dedup::parse(name)?;
+                }
+                "--height" => {
+                    let height = args.next().ok_or(SearchError{ cause: "Missing value for '--height'".to_owned() })?;
+                    options.height = Some(height.parse().ok().filter(|h| *h > 0).ok_or(SearchError{
+                        cause: format!("Invalid list height '{}'", height),
+                    })?);
+                }
+                "--mode" => {
+                    let name = args.next().ok_or(SearchError{ cause: "Missing value for '--mode'".to_owned() })?;
+                    options.mode = Mode::parse(name)?;
+                }
+                "--rank" => {
+                    let name = args.next().ok_or(SearchError{ cause: "Missing value for '--rank'".to_owned() })?;
+                    options.rank = Rank::parse(name)?;
+                }
+                "--scope" => {
+                    let name = args.next().ok_or(SearchError{ cause: "Missing value for '--scope'".to_owned() })?;
+                    options.scope = Scope::parse(name)?;
+                }
+                "--format" => {
+                    let name = args.next().ok_or(SearchError{ cause: "Missing value for '--format'".to_owned() })?;
+                    options.format = Format::parse(name)?;
+                }
+                "--fields" => {
+                    let names = args.next().ok_or(SearchError{ cause: "Missing value for '--fields'".to_owned() })?;
+                    options.fields = Field::parse_list(names)?;
+                }
+                "--limit" => {
+                    let limit = args.next().ok_or(SearchError{ cause: "Missing value for '--limit'".to_owned() })?;
+                    options.limit = limit.parse().ok().filter(|l| *l > 0).ok_or(SearchError{
+                        cause: format!("Invalid result limit '{}'", limit),
+                    })?;
+                }
+                "--reverse" => options.reverse = true,
+                "--session" => {
+                    let session = args.next().ok_or(SearchError{ cause: "Missing value for '--session'".to_owned() })?;
+                    options.session = Some(session.clone());
+                }
+                _ => query.push(arg.clone()),
+            }
+        }
+        options.query = query.join(" ");
+        Ok(options)
+    }
+}
+
+pub struct SearchError {
+    pub cause: String,
+}
+
+impl From<std::io::Error> for SearchError {
+    fn from(err: std::io::Error) -> Self {
+        SearchError { cause: format!("IO Error encountered: {}", err) }
+    }
+}
+
+impl From<rusqlite::Error> for SearchError {
+    fn from(err: rusqlite::Error) -> Self {
+        SearchError { cause: format!("SQL Error encountered: {}", err) }
+    }
+}
+
+/// Registers `REGEXP` so regex searches can run inside SQLite, compiled patterns are
+/// cached by SQLite between rows of the same statement
+pub fn register_functions(index: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+    // the built-in lower() only folds ASCII, the fuzzy prefilter must not drop what the scorer matches
+    index.create_scalar_function("fold_case", 1, FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC, |ctx| {
+        Ok(ctx.get::<Option<String>>(0)?.map(|text| fuzzy::fold_case(&text)))
+    })?;
+    index.create_scalar_function("regexp", 2, FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC, |ctx| {
+        let regex: std::sync::Arc<Regex> = match ctx.get_aux(0)? {
+            Some(regex) => regex,
+            None => {
+                let pattern = ctx.get::<String>(0)?;
+                let regex = Regex::new(&pattern).map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+                ctx.set_aux(0, regex)?
+            }
+        };
+        Ok(ctx.get::<Option<String>>(1)?.map(|text| regex.is_match(&text)).unwrap_or(false))
+    })
+}
+
+/// The `history_fts` table is created by an optional migration, see `migrate::Migration`
+pub fn fts_available(index: &rusqlite::Connection) -> Result<bool, rusqlite::Error> {
+    index.query_row(
+        "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'history_fts'",
+        [],
+        |row| row.get::<_, u32>(0),
+    ).map(|count| count > 0)
+}
+
+fn char_range(text: &str, start: usize, end: usize) -> Vec<usize> {
+    let first = text[..start].chars().count();
+    (first..first + text[start..end].chars().count()).collect()
+}
+
+/// Char positions to highlight for a command that is already known to match
+fn highlight(mode: Mode, query: &str, command: &str) -> Vec<usize> {
+    match mode {
+        Mode::Substring => command.find(query).map(|start| char_range(command, start, start + query.len())).unwrap_or_default(),
+        Mode::Prefix => (0..query.chars().count().min(command.chars().count())).collect(),
+        Mode::Regex => Regex::new(query).ok()
+            .and_then(|regex| regex.find(command).map(|m| char_range(command, m.start(), m.end())))
+            .unwrap_or_default(),
+        Mode::Fuzzy => fuzzy::score(query, command).map(|m| m.positions).unwrap_or_default(),
+    }
+}
+
+fn row_to_result(prev: Cursor, row: &rusqlite::Row) -> Result<(String, Cursor), rusqlite::Error> {
+    let cmd = row.get::<_, String>(1)?;
+    let cursor = Cursor{
+        oid: row.get(0).unwrap_or(prev.oid),
+        ..prev
+    };
+
+    Ok((cmd, cursor))
+}
+
+/// Escapes LIKE wildcards so every query char must appear literally, in order
+fn subsequence_pattern(query: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in query.chars() {
+        if c == '%' || c == '_' || c == '\\' {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+        pattern.push('%');
+    }
+    pattern
+}
+
+/// WHERE condition over `history` for `query` in `mode` and the value it expects, bound as
+/// `:pattern` in fuzzy mode and as `:query` otherwise
+fn match_condition(deps: &DataStores, query: &str, mode: Mode) -> Result<(String, String), SearchError> {
+    match mode {
+        Mode::Fuzzy if fuzzy::case_sensitive(query) => Ok(("command LIKE :pattern ESCAPE '\\'".to_owned(), subsequence_pattern(query))),
+        Mode::Fuzzy => Ok(("fold_case(command) LIKE fold_case(:pattern) ESCAPE '\\'".to_owned(), subsequence_pattern(query))),
+        Mode::Regex => {
+            Regex::new(query).map_err(|e| SearchError{ cause: format!("Invalid regex: {}", e) })?;
+            Ok((mode.condition().to_owned(), query.to_owned()))
+        }
+        _ if mode.indexed(query, deps.fts) => {
+            Ok((format!("oid IN (SELECT rowid FROM history_fts WHERE {}) AND {}", FTS_PHRASE, mode.condition()), query.to_owned()))
+        }
+        _ => Ok((mode.condition().to_owned(), query.to_owned())),
+    }
+}
+
+/// Ranks the most recent subsequence matches by fuzzy score, best first
+pub fn find_fuzzy_matches(deps: DataStores, query: &str, filter: &Filter, limit: usize) -> Result<Vec<Match>, SearchError> {
+    if query.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let (condition, pattern) = match_condition(&deps, query, Mode::Fuzzy)?;
+    let index = deps.index.try_lock().unwrap();
+    let mut statement = index.prepare(&format!(r#"
+        SELECT oid, command
+        FROM history
+        WHERE {}{}
+        ORDER BY oid DESC
+        LIMIT :limit
+    "#, condition, filter.sql()))?;
+
+    let rows = statement.query_map(
+        filter.bind(&[(":pattern", &pattern), (":limit", &FUZZY_CANDIDATES)]).as_slice(),
+        |row| Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?)),
+    )?.collect::<Result<Vec<_>, _>>()?;
+
+    let total = rows.len().max(1) as i64;
+    let mut ranked: Vec<(i64, Match)> = rows.into_iter().enumerate().filter_map(|(age, (oid, command))| {
+        let scored = fuzzy::score(query, &command)?;
+        let recency = FUZZY_RECENCY * (total - age as i64) / total;
+        Some((scored.score + recency, Match{ oid, command, positions: scored.positions }))
+    }).collect();
+
+    ranked.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.oid.cmp(&a.1.oid)));
+    Ok(ranked.into_iter().take(limit).map(|(_, m)| m).collect())
+}
+
+pub fn find_next_match(deps: DataStores, query: String, mode: Mode, filter: &Filter, cursor: Cursor) -> Result<(Option<Match>, Cursor), SearchError> {
+    if query.is_empty() && filter.is_empty() {
+        return Ok((None, cursor));
+    }
+    let mode = mode.for_query(&query);
+
+    if mode == Mode::Fuzzy {
+        let matches = find_fuzzy_matches(deps, &query, filter, cursor.rank + 1)?;
+        return Ok((matches.get(cursor.rank).cloned(), cursor));
+    }
+    if mode == Mode::Regex && Regex::new(&query).is_err() {
+        return Ok((None, cursor));
+    }
+
+    // reading from the index directly lets FTS5 walk rowids in order and stop at the first match
+    let selection = if mode.indexed(&query, deps.fts) && filter.is_empty() {
+        format!("FROM history_fts WHERE {} AND {}", FTS_PHRASE, mode.condition())
+    } else if mode.indexed(&query, deps.fts) {
+        // the filter columns live in history, checked per candidate so the index still drives the order
+        format!(
+            "FROM history_fts WHERE {} AND {} AND EXISTS (SELECT 1 FROM history WHERE history.rowid = history_fts.rowid{})",
+            FTS_PHRASE, mode.condition(), filter.sql(),
+        )
+    } else {
+        format!("FROM history WHERE {}{}", mode.condition(), filter.sql())
+    };
+    let oid = match (cursor.navigated, cursor.direction) {
+        (true, _) => cursor.oid,
+        (false, Direction::Older) => u32::MAX,
+        (false, Direction::Newer) => 0,
+    };
+    let params = filter.bind(&[(":query", &query), (":oid", &oid)]);
+
+    let result = match cursor.direction {
+        Direction::Older => {
+            deps.index.try_lock().unwrap().query_row(
+                &format!(r#"
+                    SELECT oid, command
+                    {}
+                    AND oid <= :oid
+                    ORDER BY oid DESC
+                    LIMIT 1
+                "#, selection),
+                params.as_slice(),
+                |row| row_to_result(cursor, row),
+            )
+        }
+        Direction::Newer => {
+            deps.index.try_lock().unwrap().query_row(
+                &format!(r#"
+                    SELECT oid, command
+                    {}
+                    AND oid >= :oid
+                    ORDER BY oid ASC
+                    LIMIT 1
+                "#, selection),
+                params.as_slice(),
+                |row| row_to_result(cursor, row),
+            )
+        }
+    };
+
+    match result {
+        Ok((cmd, next)) => {
+            let positions = highlight(mode, &query, &cmd);
+            Ok((Some(Match{ oid: next.oid, command: cmd, positions }), next))
+        }
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            Ok((None, cursor))
+        }
+        Err(e) => {
+            Err(e.into())
+        }
+    }
+}
+
+/// Collects up to `limit` matches in the order `find_next_match` steps through them,
+/// returning the cursor to continue from
+pub fn find_matches(deps: DataStores, query: &str, mode: Mode, filter: &Filter, cursor: Cursor, limit: usize) -> Result<(Vec<Match>, Cursor), SearchError> {
+    let mode = mode.for_query(query);
+    if mode == Mode::Fuzzy {
+        let matches: Vec<Match> = find_fuzzy_matches(deps, query, filter, cursor.rank + limit)?.into_iter().skip(cursor.rank).collect();
+        let rank = cursor.rank + matches.len();
+        return Ok((matches, Cursor{ rank, ..cursor }));
+    }
+
+    let mut matches = vec![];
+    let mut cursor = cursor;
+    while matches.len() < limit {
+        let (found, next) = find_next_match(deps.clone(), query.to_owned(), mode, filter, cursor)?;
+        let found = match found {
+            Some(found) => found,
+            None => break,
+        };
+        cursor = Cursor{ navigated: true, oid: next.oid.saturating_sub(1), ..next };
+        matches.push(found);
+        if next.oid == 0 {
+            break;
+        }
+    }
+    Ok((matches, cursor))
+}
+
+fn find_entry(deps: &DataStores, oid: u32) -> Result<Entry, SearchError> {
+    Ok(deps.index.try_lock().unwrap().query_row(
+        &format!("SELECT {} FROM history WHERE oid = :oid", record::ENTRY_COLUMNS),
+        named_params!{ ":oid": oid },
+        |row| record::row_to_entry(row, 0),
+    )?)
+}
+
+/// Number of times `command` was run, matches on the whole command so it can use the index
+/// Runs of `command` that `filter` keeps, so scoped listings count only the runs they search
+fn count_runs(deps: &DataStores, command: &str, filter: &Filter) -> Result<u32, SearchError> {
+    Ok(deps.index.try_lock().unwrap().query_row(
+        &format!("SELECT count(*) FROM history WHERE command = :command{}", filter.sql()),
+        filter.bind(&[(":command", &command)]).as_slice(),
+        |row| row.get(0),
+    )?)
+}
+
+/// Newest `limit` matches first, fuzzy matches are ordered best first instead
+pub fn find_recent_matches(deps: DataStores, query: String, mode: Mode, filter: &Filter, limit: usize) -> Result<Vec<(u32, String)>, SearchError> {
+    if query.is_empty() && filter.is_empty() {
+        return Ok(vec![]);
+    }
+    let mode = mode.for_query(&query);
+    if mode == Mode::Fuzzy {
+        return Ok(find_fuzzy_matches(deps, &query, filter, limit)?.into_iter().map(|m| (m.oid, m.command)).collect());
+    }
+    let (condition, _) = match_condition(&deps, &query, mode)?;
+
+    let index = deps.index.try_lock().unwrap();
+    let mut statement = index.prepare(&format!(r#"
+        SELECT oid, command
+        FROM history
+        WHERE {}{}
+        ORDER BY timestamp DESC, oid DESC
+        LIMIT :limit
+    "#, condition, filter.sql()))?;
+
+    let rows = statement.query_map(
+        filter.bind(&[(":query", &query), (":limit", &limit)]).as_slice(),
+        |row| {
+            Ok((
+                row.get::<_, u32>(0)?,
+                row.get::<_, String>(1)?,
+            ))
+        },
+    )?;
+
+    let mut choices = vec![];
+    for row in rows {
+        choices.push(row?);
+    }
+    Ok(choices)
+}
+
+/// Renders at most `max_width` columns of `cmd`, highlighting the chars at `positions`
+fn render_match(cmd: &str, positions: &[usize], max_width: usize, palette: &Palette) -> (String, String) {
+    let mut plain = String::new();
+    let mut styled = String::new();
+    let mut width = 0;
+    for (i, c) in cmd.chars().enumerate() {
+        // newlines are kept so multi-line commands still render as several lines
+        width += if c == '\n' { 0 } else { c.width().unwrap_or(0) };
+        if width > max_width {
+            plain.push_str("...");
+            styled.push_str("...");
+            break;
+        }
+        plain.push(c);
+        if positions.binary_search(&i).is_ok() {
+            styled.push_str(&format!("{}{}{}", palette.highlight, c, palette.reset));
+        } else {
+            styled.push(c);
+        }
+    }
+    (plain, styled)
+}
+
+fn term_size(tty: &std::fs::File) -> Result<TermSize, SearchError> {
+    unsafe {
+        let mut size: TermSize = std::mem::zeroed();
+        if ioctl(tty.as_raw_fd(), TIOCGWINSZ, &mut size as *mut _) == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(size)
+    }
+}
+
+/// Keeps the trailing end of long paths since that is the part that tells them apart
+fn shorten_cwd(cwd: &str, width: usize) -> String {
+    let home = std::env::var("HOME").unwrap_or_default();
+    let cwd = match cwd.strip_prefix(home.as_str()) {
+        Some(rest) if !home.is_empty() => format!("~{}", rest),
+        _ => cwd.to_owned(),
+    };
+    let chars: Vec<char> = cwd.chars().collect();
+    if chars.len() <= width {
+        return cwd;
+    }
+    let mut short = String::from("…");
+    short.extend(&chars[chars.len() + 1 - width..]);
+    short
+}
+
+fn render_row(row: &Row, now: u32, width: usize, selected: bool, counts: bool, palette: &Palette) -> String {
+    let (found, entry) = (&row.found, &row.entry);
+    let count = match row.count {
+        _ if !counts => String::new(),
+        1 => "     ".to_owned(),
+        n => format!("{:>4}x", n),
+    };
+    let exit = match entry.exit_code {
+        Some(0) => format!("{}{:>3}{}", palette.success, 0, palette.reset),
+        Some(code) => format!("{}{:>3}{}", palette.error, code, palette.reset),
+        None => "   ".to_owned(),
+    };
+    let cwd = shorten_cwd(entry.cwd.as_deref().unwrap_or(""), CWD_WIDTH);
+
+    // marker, age, run count, exit code and cwd columns plus the separating spaces and the truncation ellipsis
+    let fixed = 2 + 4 + count.len() + 1 + 3 + 1 + CWD_WIDTH + 1 + 3;
+    let command = found.command.replace(['\n', '\t'], " ");
+    let (_, styled) = render_match(&command, &found.positions, width.saturating_sub(fixed), palette);
+
+    let (marker, background) = if selected {
+        ("> ".to_owned(), palette.selection.clone())
+    } else {
+        ("  ".to_owned(), String::new())
+    };
+    format!(
+        "{}{}{}{:>4}{}{} {} {}{:<width$}{} {}{}",
+        background, marker, palette.muted, dates::ago(entry.timestamp, now), count, palette.reset,
+        exit, palette.directory, cwd, palette.reset, styled, style::Reset,
+        width = CWD_WIDTH,
+    )
+}
+
+/// A line in list mode, identical commands are collapsed into a single row unless dedup is off
+struct Row {
+    found: Match,
+    /// newest run of the command, the age and metadata shown come from it
+    entry: Entry,
+    count: u32,
+}
+
+/// Matches shown in list mode, fetched as the selection scrolls further down
+struct Listing {
+    query: String,
+    mode: Mode,
+    filter: Filter,
+    rank: Rank,
+    dedup: Dedup,
+    rows: Vec<Row>,
+    seen: HashSet<String>,
+    next: Cursor,
+    exhausted: bool,
+}
+
+impl Listing {
+    fn new(query: &str, mode: Mode, filter: Filter, rank: Rank, dedup: Dedup) -> Self {
+        let exhausted = query.is_empty() && filter.is_empty();
+        Listing{
+            query: query.to_owned(),
+            mode,
+            filter,
+            rank,
+            dedup,
+            rows: vec![],
+            seen: HashSet::new(),
+            next: Cursor{ direction: Direction::Older, navigated: false, oid: u32::MAX, rank: 0 },
+            exhausted,
+        }
+    }
+
+    fn push(&mut self, deps: &DataStores, found: Match) -> Result<(), SearchError> {
+        match self.dedup {
+            Dedup::Off => {}
+            Dedup::Consecutive => {
+                if let Some(last) = self.rows.last_mut().filter(|last| last.found.command == found.command) {
+                    last.count += 1;
+                    return Ok(());
+                }
+            }
+            Dedup::Global => {
+                if !self.seen.insert(found.command.clone()) {
+                    return Ok(());
+                }
+            }
+        }
+
+        let entry = find_entry(deps, found.oid)?;
+        let count = if self.dedup == Dedup::Global { count_runs(deps, &found.command, &self.filter)? } else { 1 };
+        self.rows.push(Row{ found, entry, count });
+        Ok(())
+    }
+
+    /// Fetches until there are `count` rows, `here` is the directory frecency favours
+    fn fill(&mut self, deps: &DataStores, count: usize, here: Option<&str>) -> Result<(), SearchError> {
+        // collapsed duplicates do not add rows, so keep fetching until there are enough
+        while !self.exhausted && self.rows.len() < count {
+            let wanted = count - self.rows.len();
+            let (matches, next) = match self.rank {
+                Rank::Recency => find_matches(deps.clone(), &self.query, self.mode, &self.filter, self.next, wanted)?,
+                Rank::Frecency => {
+                    let matches = find_frecent_matches(deps, &self.query, self.mode, &self.filter, here, self.next.rank, wanted)?;
+                    let next = Cursor{ rank: self.next.rank + matches.len(), ..self.next };
+                    (matches, next)
+                }
+            };
+            self.exhausted = matches.len() < wanted;
+            self.next = next;
+            for found in matches {
+                self.push(deps, found)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits the prompt into text and filters on top of the scope, a malformed query searches
+/// nothing and returns the error to show in place of the matches
+fn parse_query(input: &str, scoped: &Filter, context: &Context, now: u32) -> (String, Filter, Option<String>) {
+    match Query::parse(input, context, now) {
+        Ok(query) => {
+            let mut filter = scoped.clone();
+            filter.extend(query.filter);
+            (query.text, filter, None)
+        }
+        Err(e) => (String::new(), Filter::default(), Some(e.cause)),
+    }
+}
+
+fn prompt(prefix: &str, mode: Mode, scope: Scope, rank: Rank) -> String {
+    let mut prompt = format!("({}", prefix);
+    if mode != Mode::Substring {
+        prompt.push(' ');
+        prompt.push_str(mode.name());
+    }
+    if rank != Rank::Recency {
+        prompt.push(' ');
+        prompt.push_str(rank.name());
+    }
+    if scope != Scope::Global {
+        prompt.push(' ');
+        prompt.push_str(scope.name());
+    }
+    prompt.push_str("): ");
+    prompt
+}
+
+/// List mode: the prompt followed by a page of the newest matches with their age, exit code and cwd,
+/// drawn inline below the cursor or on the alternate screen with a preview of the selection
+fn interactive_list(deps: DataStores, options: &Options, tty: &mut std::fs::File, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<Option<String>, SearchError> {
+    let mut alternate;
+    let (writer, mut init): (&mut dyn Write, Position) = if options.fullscreen {
+        screen::restore_on_panic();
+        alternate = AlternateScreen::from(writer);
+        (&mut alternate, Position{ x: 1, y: 1 })
+    } else {
+        let init = tty.cursor_pos().map(|(x, y)| Position{x, y})?;
+        (writer, init)
+    };
+    let now = record::now().map_err(|e| SearchError{ cause: e.cause })?;
+
+    let mut editor = Editor::new(&options.query);
+    let mut mode = options.mode;
+    let context = Context::current(options.session.as_deref());
+    let mut scope = options.scope;
+    let mut scoped = scope.filter(&context)?;
+    let mut rank = options.rank;
+    let mut listing = Listing::new("", mode, scoped.clone(), rank, options.dedup);
+    let mut selected = 0;
+    let mut scroll = 0;
+
+    let mut input = reader.keys();
+    loop {
+        let query = editor.text().to_owned();
+        let (text, filter, error) = parse_query(&query, &scoped, &context, now);
+        let size = term_size(tty)?;
+        let height = if options.fullscreen {
+            screen::list_height(size.ws_row)
+        } else {
+            options.height.unwrap_or(size.ws_row).min(size.ws_row.saturating_sub(1)).max(1)
+        };
+
+        // make room for the list by scrolling the terminal when the prompt starts near the bottom
+        if !options.fullscreen && init.y + height > size.ws_row {
+            let missing = init.y + height - size.ws_row;
+            write!(writer, "{}{}", cursor::Goto(1, size.ws_row), "\n".repeat(missing as usize))?;
+            init.y = init.y.saturating_sub(missing).max(1);
+        }
+        let height = height as usize;
+
+        if listing.query != text || listing.mode != mode || listing.filter != filter || listing.rank != rank {
+            listing = Listing::new(&text, mode, filter, rank, options.dedup);
+            selected = 0;
+            scroll = 0;
+        }
+        listing.fill(&deps, selected + 1, context.cwd.as_deref())?;
+        selected = selected.min(listing.rows.len().saturating_sub(1));
+        if selected < scroll {
+            scroll = selected;
+        } else if selected >= scroll + height {
+            scroll = selected + 1 - height;
+        }
+        listing.fill(&deps, scroll + height, context.cwd.as_deref())?;
+
+        let prompt_prefix = prompt(&options.prompt_prefix, mode, scope, rank);
+        if options.fullscreen {
+            let status = vec![
+                format!("mode: {}", mode.name()),
+                format!("scope: {}", scope.name()),
+                format!("rank: {}", rank.name()),
+                format!("dedup: {}", options.dedup.name()),
+                format!("match {}/{}{}", selected + 1, listing.rows.len(), if listing.exhausted { "" } else { "+" }),
+            ];
+            init.y = screen::draw(writer, &size, &screen::Frame{
+                prompt: &prompt_prefix,
+                query: &query,
+                rows: &listing.rows,
+                counts: options.dedup != Dedup::Off,
+                scroll,
+                selected,
+                status: &status,
+                error: error.as_deref(),
+                now,
+                palette: &options.palette,
+            })?;
+        } else {
+            write!(writer, "{}{}", cursor::Goto(init.x, init.y), clear::AfterCursor)?;
+            let palette = &options.palette;
+            write!(writer, "{}{}{}{}", palette.prompt, prompt_prefix, style::Reset, query)?;
+            for (row, line) in listing.rows.iter().skip(scroll).take(height).enumerate() {
+                let line = render_row(line, now, size.ws_col as usize, scroll + row == selected, options.dedup != Dedup::Off, palette);
+                write!(writer, "{}{}", cursor::Goto(1, init.y + 1 + row as u16), line)?;
+            }
+            if let Some(error) = error.as_ref() {
+                write!(writer, "{}{}{}{}", cursor::Goto(1, init.y + 1), palette.error, error, style::Reset)?;
+            } else if listing.rows.is_empty() {
+                write!(writer, "{}{}<no match>{}", cursor::Goto(1, init.y + 1), palette.muted, style::Reset)?;
+            }
+        }
+
+        let column = init.x + (prompt_prefix.width() + editor.cursor_width()) as u16;
+        write!(writer, "{}", cursor::Goto(column, init.y))?;
+        writer.flush()?;
+
+        let next = input.next().ok_or(
+            SearchError{ cause: "Error occured while waiting on input".to_owned() }
+        )?;
+
+        match next? {
+            Key::Esc | Key::Ctrl('d') |
+            Key::Char('\n') => {
+                break;
+            }
+            Key::Ctrl('c') | Key::Ctrl('g') => {
+                listing.rows.clear();
+                break;
+            }
+            Key::Ctrl('r') | Key::Ctrl('n') | Key::Down => {
+                selected += 1;
+            }
+            Key::Ctrl('p') | Key::Up => {
+                selected = selected.saturating_sub(1);
+            }
+            Key::PageDown => {
+                selected += height;
+            }
+            Key::PageUp => {
+                selected = selected.saturating_sub(height);
+            }
+            Key::Ctrl('t') => {
+                mode = mode.next();
+            }
+            Key::Ctrl('x') => {
+                scope = scope.next(&context);
+                scoped = scope.filter(&context)?;
+            }
+            Key::Ctrl('o') => {
+                rank = rank.toggle();
+            }
+            key => {
+                if !editor.handle(&key) {
+                    log::log!(log::Level::Debug, "input '{:?}' was ignored", key);
+                }
+            }
+        };
+    }
+
+    write!(writer, "{}{}", cursor::Goto(init.x, init.y), clear::AfterCursor)?;
+    writer.flush()?;
+
+    Ok(listing.rows.get(selected).map(|row| row.found.command.clone()))
+}
+
+pub fn interactive(deps: DataStores, options: &Options, tty: &mut std::fs::File, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<Option<String>, SearchError> {
+    if options.list || options.fullscreen {
+        return interactive_list(deps, options, tty, reader, writer);
+    }
+
+    let mut size: TermSize;
+    let mut init = tty.cursor_pos().map(|(x, y)| Position{x, y})?;
+
+    let context = Context::current(options.session.as_deref());
+    let mut editor = Editor::new(&options.query);
+    let mut search = incremental::Incremental::new(options.dedup, context.cwd.clone());
+    let mut cancelled = false;
+
+    let mut input = reader.keys();
+    let mut running = true;
+    let mut mode = options.mode;
+    let mut scope = options.scope;
+    let mut scoped = scope.filter(&context)?;
+    let mut rank = options.rank;
+    let now = record::now().map_err(|e| SearchError{ cause: e.cause })?;
+
+    let palette = &options.palette;
+    let search_prefix = options.search_prefix.as_str();
+    let max_width = options.max_width;
+    while running {
+        let query = editor.text().to_owned();
+        let (text, filter, error) = parse_query(&query, &scoped, &context, now);
+        search.update(&deps, &text, mode, &filter, rank)?;
+
+        let mut prompt_prefix = prompt(&options.prompt_prefix, mode, scope, rank);
+        if search.failing {
+            prompt_prefix.insert_str(0, "failing ");
+        }
+        write!(writer, "{}{}", cursor::Goto(init.x, init.y), clear::AfterCursor)?;
+        let prompt_color = if search.failing { &palette.error } else { &palette.prompt };
+        write!(writer, "{}{}{}{}\n{}", prompt_color, prompt_prefix, style::Reset, query, search_prefix)?;
+
+        let rendered_text = search.current.as_ref().map(|m| render_match(&m.command, &m.positions, max_width, palette));
+
+        if let Some(error) = error.as_ref() {
+            write!(writer, "{}{}{}", palette.error, error, style::Reset)?;
+        } else if let Some((_, styled)) = rendered_text.clone() {
+            write!(writer, "{}", styled)?;
+        } else {
+            write!(writer, "{}<no match>{}", palette.muted, style::Reset)?;
+        }
+
+        write!(writer, "{}", cursor::Goto(init.x + (prompt_prefix.width() + editor.cursor_width()) as u16, init.y))?;
+        writer.flush()?;
+
+        let next = input.next().ok_or(
+            SearchError{ cause: "Error occured while waiting on input".to_owned() }
+        )?;
+
+        match next? {
+            Key::Esc | Key::Ctrl('d') |
+            Key::Char('\n') => {
+                running = false;
+            }
+            Key::Ctrl('c') | Key::Ctrl('g') => {
+                cancelled = true;
+                running = false;
+            }
+            Key::Ctrl('r') | Key::Up | Key::PageUp => {
+                search.step(&deps, Direction::Older)?;
+            }
+            Key::Ctrl('s') | Key::Down | Key::PageDown => {
+                search.step(&deps, Direction::Newer)?;
+            }
+            Key::Ctrl('t') => {
+                mode = mode.next();
+            }
+            Key::Ctrl('x') => {
+                scope = scope.next(&context);
+                scoped = scope.filter(&context)?;
+            }
+            Key::Ctrl('o') => {
+                rank = rank.toggle();
+            }
+            key => {
+                if !editor.handle(&key) {
+                    log::log!(log::Level::Debug, "input '{:?}' was ignored", key);
+                }
+            }
+        };
+
+        // recalculate restore position if the window dimensions changed due to scrolling
+        size = term_size(tty)?;
+
+        if init.y == size.ws_row {
+            init.y = size.ws_row - 1;
+        }
+
+        if let Some((cmd_text, _)) = rendered_text.clone() {
+            let mut combined = search_prefix.to_owned();
+            combined.push_str(cmd_text.as_str());
+
+            let mut rows: u16 = 0;
+            for line in combined.split('\n') {
+                rows += 1;
+                rows += line.width() as u16 / size.ws_col;
+            }
+            if rows + init.y >= size.ws_row {
+                init.y = size.ws_row - rows;
+            }
+        }
+    }
+
+    write!(writer, "{}{}", cursor::Goto(init.x, init.y), clear::AfterCursor)?;
+    writer.flush()?;
+
+    if cancelled {
+        return Ok(None);
+    }
+    Ok(search.current.map(|m| m.command))
+}
+
+/// An in-memory index holding `entries` in order for tests, searches never write to the archive
+#[cfg(test)]
+fn fixture(entries: &[Entry]) -> DataStores {
+    let mut index = rusqlite::Connection::open_in_memory().unwrap();
+    super::migrate::migrate(&mut index).unwrap_or_else(|e| panic!("{}", e.cause));
+    index.execute("PRAGMA case_sensitive_like=ON", named_params!{}).unwrap();
+    register_functions(&index).unwrap();
+    for entry in entries.iter() {
+        record::index_entry(&index, entry).unwrap();
+    }
+    let fts = fts_available(&index).unwrap();
+    DataStores{
+        home: std::env::temp_dir(),
+        index: std::sync::Arc::new(std::sync::Mutex::new(index)),
+        archive: std::fs::File::open("/dev/null").unwrap(),
+        fts,
+        key: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(command: &str, session: &str) -> Entry {
+        let mut entry = Entry::new(command.to_owned(), 1_700_000_000);
+        entry.session = Some(session.to_owned());
+        entry
+    }
+
+    #[test]
+    fn fuzzy_matches_fold_beyond_ascii() {
+        let deps = fixture(&[Entry::new("echo CAFÉ".to_owned(), 1_700_000_000), Entry::new("echo cafe".to_owned(), 1_700_000_000)]);
+        let found: Vec<String> = find_fuzzy_matches(deps, "café", &Filter::default(), 10)
+            .unwrap_or_else(|e| panic!("{}", e.cause))
+            .into_iter()
+            .map(|m| m.command)
+            .collect();
+        assert_eq!(found, ["echo CAFÉ"]);
+    }
+
+    #[test]
+    fn listed_run_counts_follow_the_scope() {
+        let deps = fixture(&[run("make", "a"), run("make", "b"), run("make", "a"), run("make test", "b")]);
+        let context = Context{ host: None, session: Some("a".to_owned()), cwd: None, repository: None };
+
+        let mut listing = Listing::new("make", Mode::Substring, Scope::Session.filter(&context).unwrap_or_else(|e| panic!("{}", e.cause)), Rank::Recency, Dedup::Global);
+        listing.fill(&deps, 10, None).unwrap_or_else(|e| panic!("{}", e.cause));
+        let counts: Vec<(&str, u32)> = listing.rows.iter().map(|row| (row.found.command.as_str(), row.count)).collect();
+        assert_eq!(counts, [("make", 2)]);
+
+        let mut listing = Listing::new("make", Mode::Substring, Scope::Global.filter(&context).unwrap_or_else(|e| panic!("{}", e.cause)), Rank::Recency, Dedup::Global);
+        listing.fill(&deps, 10, None).unwrap_or_else(|e| panic!("{}", e.cause));
+        let counts: Vec<(&str, u32)> = listing.rows.iter().map(|row| (row.found.command.as_str(), row.count)).collect();
+        assert_eq!(counts, [("make test", 1), ("make", 3)]);
+    }
+}
@@ -0,0 +1,171 @@
+// Non-interactive results for scripts. `plain` keeps the historical `oid command` lines,
+// `tsv` escapes tabs, newlines and backslashes so every record is one line, `null` ends
+// each record with a NUL byte and leaves fields untouched for `fzf --read0`, and `json`
+// and `jsonl` write objects keyed by field name.
+
+use std::io::Write;
+
+use serde_json::Value;
+
+use super::super::history::Entry;
+use super::super::init::DataStores;
+use super::super::record;
+use super::{find_entry, find_frecent_matches, find_recent_matches, Context, Options, Query, Rank, SearchError};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Plain,
+    Json,
+    JsonLines,
+    Tsv,
+    Null,
+}
+
+pub const FORMATS: [&str; 5] = ["plain", "json", "jsonl", "tsv", "null"];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Field {
+    Oid,
+    Time,
+    End,
+    Exit,
+    Cwd,
+    Host,
+    User,
+    Session,
+    Command,
+}
+
+pub const FIELDS: [&str; 9] = ["oid", "time", "end", "exit", "cwd", "host", "user", "session", "command"];
+
+impl Format {
+    pub fn parse(name: &str) -> Result<Self, SearchError> {
+        match name {
+            "plain" => Ok(Format::Plain),
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::JsonLines),
+            "tsv" => Ok(Format::Tsv),
+            "null" => Ok(Format::Null),
+            _ => Err(SearchError{ cause: format!("Unknown output format '{}', expected one of {}", name, FORMATS.join(", ")) }),
+        }
+    }
+}
+
+impl Field {
+    pub fn parse(name: &str) -> Result<Self, SearchError> {
+        match name {
+            "oid" => Ok(Field::Oid),
+            "time" => Ok(Field::Time),
+            "end" => Ok(Field::End),
+            "exit" => Ok(Field::Exit),
+            "cwd" => Ok(Field::Cwd),
+            "host" => Ok(Field::Host),
+            "user" => Ok(Field::User),
+            "session" => Ok(Field::Session),
+            "command" => Ok(Field::Command),
+            _ => Err(SearchError{ cause: format!("Unknown field '{}', expected one of {}", name, FIELDS.join(", ")) }),
+        }
+    }
+
+    /// Parses a comma separated list such as `time,cwd,command`
+    pub fn parse_list(names: &str) -> Result<Vec<Self>, SearchError> {
+        names.split(',').map(|name| Field::parse(name.trim())).collect()
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Field::Oid => "oid",
+            Field::Time => "time",
+            Field::End => "end",
+            Field::Exit => "exit",
+            Field::Cwd => "cwd",
+            Field::Host => "host",
+            Field::User => "user",
+            Field::Session => "session",
+            Field::Command => "command",
+        }
+    }
+
+    fn value(self, oid: u32, entry: &Entry) -> Value {
+        match self {
+            Field::Oid => oid.into(),
+            Field::Time => entry.timestamp.into(),
+            Field::End => entry.end_timestamp.into(),
+            Field::Exit => entry.exit_code.into(),
+            Field::Cwd => entry.cwd.clone().into(),
+            Field::Host => entry.hostname.clone().into(),
+            Field::User => entry.user.clone().into(),
+            Field::Session => entry.session.clone().into(),
+            Field::Command => entry.command.clone().into(),
+        }
+    }
+}
+
+/// Text of a field for the delimited formats, missing values are empty
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn tsv_field(field: &str) -> String {
+    field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+/// Fields as a JSON object, written by hand so the keys keep the requested order
+fn json_object(fields: &[Field], values: &[Value]) -> String {
+    let members: Vec<String> = fields.iter().zip(values)
+        .map(|(field, value)| format!("{}:{}", Value::from(field.name()), value))
+        .collect();
+    format!("{{{}}}", members.join(","))
+}
+
+fn write_results(writer: &mut dyn Write, format: Format, fields: &[Field], results: &[(u32, Entry)]) -> std::io::Result<()> {
+    if format == Format::Json {
+        write!(writer, "[")?;
+    }
+    for (n, (oid, entry)) in results.iter().enumerate() {
+        let values: Vec<Value> = fields.iter().map(|field| field.value(*oid, entry)).collect();
+        match format {
+            Format::Plain => writeln!(writer, "{}", values.iter().map(text).collect::<Vec<_>>().join(" "))?,
+            Format::Tsv => writeln!(writer, "{}", values.iter().map(|v| tsv_field(&text(v))).collect::<Vec<_>>().join("\t"))?,
+            Format::Null => write!(writer, "{}\0", values.iter().map(text).collect::<Vec<_>>().join("\t"))?,
+            Format::JsonLines => writeln!(writer, "{}", json_object(fields, &values))?,
+            Format::Json => write!(writer, "{}{}", if n == 0 { "" } else { "," }, json_object(fields, &values))?,
+        }
+    }
+    if format == Format::Json {
+        writeln!(writer, "]")?;
+    }
+    writer.flush()
+}
+
+/// Runs a non-interactive search and writes the results. The best match is written last so it
+/// sits nearest the prompt, `--reverse` writes it first
+pub fn print(deps: DataStores, options: &Options, writer: &mut dyn Write) -> Result<(), SearchError> {
+    let context = Context::current(options.session.as_deref());
+    let now = record::now().map_err(|e| SearchError{ cause: e.cause })?;
+    let query = Query::parse(&options.query, &context, now)?;
+    let mut filter = options.scope.filter(&context)?;
+    filter.extend(query.filter);
+
+    let matches = match options.rank {
+        Rank::Recency => find_recent_matches(deps.clone(), query.text, options.mode, &filter, options.limit)?,
+        Rank::Frecency => find_frecent_matches(&deps, &query.text, options.mode, &filter, context.cwd.as_deref(), 0, options.limit)?
+            .into_iter()
+            .map(|m| (m.oid, m.command))
+            .collect(),
+    };
+
+    let mut results = vec![];
+    for (oid, _) in matches {
+        results.push((oid, find_entry(&deps, oid)?));
+    }
+    if !options.reverse {
+        results.reverse();
+    }
+    write_results(writer, options.format, &options.fields, &results)?;
+    Ok(())
+}
@@ -0,0 +1,39 @@
+// Escape sequences for each part of the search UI, taken from the `colors` settings. All of
+// them are empty when colors are disabled.
+
+use termion::color;
+
+use super::super::config::{Color, Colors};
+
+#[derive(Clone, Debug, Default)]
+pub struct Palette {
+    pub prompt: String,
+    pub error: String,
+    pub highlight: String,
+    pub muted: String,
+    pub directory: String,
+    pub success: String,
+    /// background of the selected row
+    pub selection: String,
+    /// restores the default foreground after any of the above
+    pub reset: String,
+}
+
+impl Palette {
+    pub fn new(colors: &Colors) -> Self {
+        if !colors.enabled {
+            return Palette::default();
+        }
+        let fg = |color: Color| color::Fg(color.termion()).to_string();
+        Palette{
+            prompt: fg(colors.prompt),
+            error: fg(colors.error),
+            highlight: fg(colors.highlight),
+            muted: fg(colors.muted),
+            directory: fg(colors.directory),
+            success: fg(colors.success),
+            selection: color::Bg(colors.selection.termion()).to_string(),
+            reset: color::Fg(color::Reset).to_string(),
+        }
+    }
+}
@@ -0,0 +1,219 @@
+// Query syntax shared by `scribe search` and the interactive prompt. The first word is
+// matched against the command using the search mode and every other word must appear in
+// it too, in any order, while `key:value` words filter on the metadata recorded with it:
+//
+//   cwd:~/src/api       run in the directory or below it, relative paths start from here
+//   host:build01        run on a host, `host:current` for this one
+//   session:current     run in a session, by id or the current one
+//   exit:0  exit:!0     exit code equal or not equal
+//   after:2026-01-01    run at or after a point in time, anything `dates::parse` accepts
+//   before:yesterday    run before a point in time
+//   dur:>5s             took longer or shorter, with >, >=, <, <= or =, bare is >=
+//
+// A leading `-` excludes commands containing the word or negates the filter. Double
+// quotes keep spaces inside a phrase, which then has to appear as written, and make a
+// word literal, so `"-rf"` is text.
+
+use std::path::Path;
+
+use rusqlite::types::Value;
+
+use super::super::dates;
+use super::{Context, Filter, SearchError};
+
+const KEYS: &[&str] = &["cwd", "host", "session", "exit", "after", "before", "dur"];
+
+pub struct Query {
+    /// first word or phrase, matched by the search mode. The others become filters.
+    pub text: String,
+    pub filter: Filter,
+}
+
+struct Token {
+    text: String,
+    /// byte offset in `text` where the first quoted part starts
+    quoted: Option<usize>,
+}
+
+impl Token {
+    /// The text before any quotes, only this part can hold a `-` or a filter key
+    fn bare(&self) -> &str {
+        &self.text[..self.quoted.unwrap_or(self.text.len())]
+    }
+}
+
+/// Splits on whitespace outside double quotes, `\"` and `\\` escape inside quotes
+fn tokenize(input: &str) -> Result<Vec<Token>, SearchError> {
+    let mut tokens = vec![];
+    let mut current: Option<Token> = None;
+    let mut quoted = false;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() && !quoted {
+            tokens.extend(current.take());
+            continue;
+        }
+        let token = current.get_or_insert_with(|| Token{ text: String::new(), quoted: None });
+        match c {
+            '"' => {
+                quoted = !quoted;
+                token.quoted.get_or_insert(token.text.len());
+            }
+            '\\' if quoted => match chars.next() {
+                Some(escaped) => token.text.push(escaped),
+                None => break,
+            },
+            c => token.text.push(c),
+        }
+    }
+    if quoted {
+        return Err(SearchError{ cause: "Unterminated quote in search query".to_owned() });
+    }
+    tokens.extend(current);
+    Ok(tokens)
+}
+
+fn current(value: &str, known: Option<&str>, what: &str) -> Result<Value, String> {
+    match value {
+        "current" => known.map(|v| Value::Text(v.to_owned())).ok_or(format!("the current {} is not known", what)),
+        _ => Ok(Value::Text(value.to_owned())),
+    }
+}
+
+/// Expands `~` and resolves relative paths against the directory the search started from
+fn directory(value: &str, context: &Context) -> Result<Value, String> {
+    let path = match value.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let home = dirs::home_dir().ok_or("unable to detect the home dir")?;
+            format!("{}{}", home.display(), rest)
+        }
+        _ if Path::new(value).is_absolute() => value.to_owned(),
+        _ => {
+            let cwd = context.cwd.as_deref().ok_or("the current directory is not known")?;
+            let joined = Path::new(cwd).join(value);
+            // only `.` and `..` are folded, the recorded cwd is never canonicalized either
+            let mut parts: Vec<&str> = vec![];
+            for part in joined.to_str().unwrap_or_default().split('/') {
+                match part {
+                    "" | "." => {}
+                    ".." => { parts.pop(); }
+                    part => parts.push(part),
+                }
+            }
+            format!("/{}", parts.join("/"))
+        }
+    };
+    Ok(Value::Text(path.trim_end_matches('/').to_owned()))
+}
+
+/// SQL for one filter using `:value` for its parameter, and the value to bind
+fn compile(key: &str, value: &str, context: &Context, now: u32) -> Result<(String, Value), String> {
+    if value.is_empty() {
+        return Err("missing value".to_owned());
+    }
+    match key {
+        // the directory itself and everything below it
+        "cwd" => Ok(("cwd = :value OR substr(cwd, 1, length(:value) + 1) = :value || '/'".to_owned(), directory(value, context)?)),
+        "host" => Ok(("hostname = :value".to_owned(), current(value, context.host.as_deref(), "host")?)),
+        "session" => Ok(("session = :value".to_owned(), current(value, context.session.as_deref(), "session")?)),
+        "exit" => {
+            let (operator, code) = match value.strip_prefix('!') {
+                Some(code) => ("<>", code),
+                None => ("=", value),
+            };
+            let code: i64 = code.parse().map_err(|_| format!("expected an exit code but found '{}'", code))?;
+            Ok((format!("exit_code {} :value", operator), Value::Integer(code)))
+        }
+        "after" => Ok(("timestamp >= :value".to_owned(), Value::Integer(dates::parse(value, now)?.into()))),
+        "before" => Ok(("timestamp < :value".to_owned(), Value::Integer(dates::parse(value, now)?.into()))),
+        _ => {
+            let operator = [">=", "<=", ">", "<", "="].iter().find(|op| value.starts_with(**op)).copied();
+            let duration = dates::parse_duration(&value[operator.map(str::len).unwrap_or(0)..])?;
+            Ok((format!("end_timestamp - timestamp {} :value", operator.unwrap_or(">=")), Value::Integer(duration.into())))
+        }
+    }
+}
+
+impl Query {
+    /// Splits `input` into the text to match and filters compiled to SQL, `now` anchors relative dates
+    pub fn parse(input: &str, context: &Context, now: u32) -> Result<Self, SearchError> {
+        let mut text = None;
+        let mut filter = Filter::default();
+        for token in tokenize(input)? {
+            let bare = token.bare();
+            let negated = bare.starts_with('-') && token.text.len() > 1;
+            let skip = if negated { 1 } else { 0 };
+            let body = &token.text[skip..];
+            let key = bare[skip..].split_once(':').map(|(key, _)| key).filter(|key| KEYS.contains(key));
+            let name = format!(":query_{}", filter.params.len());
+
+            let (clause, value) = match key {
+                Some(key) => compile(key, &body[key.len() + 1..], context, now).map_err(|reason| SearchError{
+                    cause: format!("Invalid filter '{}': {}", token.text, reason),
+                })?,
+                None if (negated || text.is_some()) && !body.is_empty() => ("instr(command, :value) > 0".to_owned(), Value::Text(body.to_owned())),
+                None => {
+                    if !body.is_empty() {
+                        text = Some(body.to_owned());
+                    }
+                    continue;
+                }
+            };
+            let clause = clause.replace(":value", &name);
+            // NULL metadata never matches a filter, so it must match its negation
+            let clause = if negated { format!("NOT coalesce(({}), 0)", clause) } else { clause };
+            filter.push(&clause, vec![(&name, value)]);
+        }
+        Ok(Query{ text: text.unwrap_or_default(), filter })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::history::Entry;
+    use super::super::{find_recent_matches, fixture, Mode};
+    use super::*;
+
+    const COMMANDS: &[&str] = &["git status", "git stash", "status git", "git log --stat", "echo git status done"];
+
+    fn context() -> Context {
+        Context{ host: None, session: None, cwd: None, repository: None }
+    }
+
+    fn search(input: &str, mode: Mode) -> Vec<String> {
+        let entries: Vec<Entry> = COMMANDS.iter().map(|command| Entry::new(command.to_string(), 1_700_000_000)).collect();
+        let query = Query::parse(input, &context(), 1_700_000_000).unwrap_or_else(|e| panic!("{}", e.cause));
+        let mut found: Vec<String> = find_recent_matches(fixture(&entries), query.text, mode, &query.filter, 10)
+            .unwrap_or_else(|e| panic!("{}", e.cause))
+            .into_iter()
+            .map(|(_, command)| command)
+            .collect();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn words_match_in_any_order() {
+        let query = Query::parse("status git", &context(), 0).unwrap_or_else(|e| panic!("{}", e.cause));
+        assert_eq!(query.text, "status");
+        assert_eq!(query.filter.clauses.len(), 1);
+
+        assert_eq!(search("status git", Mode::Substring), ["echo git status done", "git status", "status git"]);
+        assert_eq!(search("git status", Mode::Substring), ["echo git status done", "git status", "status git"]);
+        assert_eq!(search("git stat", Mode::Substring), ["echo git status done", "git log --stat", "git status", "status git"]);
+        // only the first word is matched by the mode
+        assert_eq!(search("git status", Mode::Prefix), ["git status"]);
+    }
+
+    #[test]
+    fn quoted_phrases_match_as_written() {
+        let query = Query::parse("\"git status\"", &context(), 0).unwrap_or_else(|e| panic!("{}", e.cause));
+        assert_eq!(query.text, "git status");
+        assert!(query.filter.clauses.is_empty());
+
+        assert_eq!(search("\"git status\"", Mode::Substring), ["echo git status done", "git status"]);
+        assert_eq!(search("\"status git\"", Mode::Substring), ["status git"]);
+        assert_eq!(search("done \"git status\"", Mode::Substring), ["echo git status done"]);
+        assert_eq!(search("\"git st\" -log -echo", Mode::Substring), ["git stash", "git status"]);
+    }
+}
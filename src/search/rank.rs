@@ -0,0 +1,200 @@
+// Frecency ranks each distinct matching command by how often and how recently it ran, so
+// a command used every day outranks one typed once an hour ago. Every run adds a weight
+// that decays with its age, failed runs count for a quarter and runs in the directory the
+// search started from count double.
+
+use rusqlite::ToSql;
+use serde::{Deserialize, Serialize};
+
+use super::super::init::DataStores;
+use super::super::record;
+use super::{highlight, match_condition, Filter, Match, Mode, SearchError};
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rank {
+    /// newest match first
+    Recency,
+    Frecency,
+}
+
+/// Weight of a single run, summed per command
+const RUN_WEIGHT: &str = r#"
+    CASE
+        WHEN :now - timestamp < 3600 THEN 4.0
+        WHEN :now - timestamp < 86400 THEN 2.0
+        WHEN :now - timestamp < 604800 THEN 1.0
+        ELSE 0.5
+    END
+    * CASE WHEN exit_code IS NULL OR exit_code = 0 THEN 1.0 ELSE 0.25 END
+    * CASE WHEN cwd = :here THEN 2.0 ELSE 1.0 END
+"#;
+
+impl Rank {
+    pub fn parse(name: &str) -> Result<Self, SearchError> {
+        match name {
+            "recency" => Ok(Rank::Recency),
+            "frecency" => Ok(Rank::Frecency),
+            _ => Err(SearchError{ cause: format!("Unknown rank '{}', expected recency or frecency", name) }),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Rank::Recency => "recency",
+            Rank::Frecency => "frecency",
+        }
+    }
+
+    pub fn toggle(self) -> Self {
+        match self {
+            Rank::Recency => Rank::Frecency,
+            Rank::Frecency => Rank::Recency,
+        }
+    }
+}
+
+/// Distinct commands matching `query` ordered by frecency, best first, skipping the first `offset`.
+/// `here` is the directory whose runs get the bonus, ties go to the most recently run command
+pub fn find_frecent_matches(
+    deps: &DataStores, query: &str, mode: Mode, filter: &Filter, here: Option<&str>, offset: usize, limit: usize,
+) -> Result<Vec<Match>, SearchError> {
+    if query.is_empty() && filter.is_empty() {
+        return Ok(vec![]);
+    }
+    let mode = mode.for_query(query);
+    let (condition, pattern) = match_condition(deps, query, mode)?;
+    let now = record::now().map_err(|e| SearchError{ cause: e.cause })?;
+
+    let index = deps.index.try_lock().unwrap();
+    let mut statement = index.prepare(&format!(r#"
+        SELECT max(oid), command, sum({}) AS score
+        FROM history
+        WHERE {}{}
+        GROUP BY command
+        ORDER BY score DESC, max(oid) DESC
+        LIMIT :limit OFFSET :offset
+    "#, RUN_WEIGHT, condition, filter.sql()))?;
+
+    let named: [(&str, &dyn ToSql); 5] = [
+        (if mode == Mode::Fuzzy { ":pattern" } else { ":query" }, &pattern),
+        (":now", &now),
+        (":here", &here),
+        (":limit", &limit),
+        (":offset", &offset),
+    ];
+    let rows = statement.query_map(
+        filter.bind(&named).as_slice(),
+        |row| Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?)),
+    )?;
+
+    let mut matches = vec![];
+    for row in rows {
+        let (oid, command) = row?;
+        let positions = highlight(mode, query, &command);
+        matches.push(Match{ oid, command, positions });
+    }
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::history::Entry;
+    use super::super::fixture;
+    use super::*;
+
+    const HOUR: u32 = 3600;
+    const DAY: u32 = 86400;
+
+    /// A run of `command` `age` seconds ago
+    struct Run {
+        command: &'static str,
+        age: u32,
+        exit_code: Option<i32>,
+        cwd: Option<&'static str>,
+    }
+
+    fn run(command: &'static str, age: u32) -> Run {
+        Run{ command, age, exit_code: Some(0), cwd: None }
+    }
+
+    fn history(runs: &[Run]) -> DataStores {
+        let now = record::now().unwrap_or_else(|e| panic!("{}", e.cause));
+        let entries: Vec<Entry> = runs.iter().map(|run| {
+            let mut entry = Entry::new(run.command.to_owned(), now - run.age);
+            entry.exit_code = run.exit_code;
+            entry.cwd = run.cwd.map(str::to_owned);
+            entry
+        }).collect();
+        fixture(&entries)
+    }
+
+    fn ranked(deps: &DataStores, here: Option<&str>) -> Vec<String> {
+        find_frecent_matches(deps, ".", Mode::Regex, &Filter::default(), here, 0, 10)
+            .unwrap_or_else(|e| panic!("{}", e.cause))
+            .into_iter()
+            .map(|m| m.command)
+            .collect()
+    }
+
+    #[test]
+    fn recent_runs_weigh_more() {
+        // one run per bucket weighs 4, 2, 1 and 0.5
+        let deps = history(&[run("month", 30 * DAY), run("week", 3 * DAY), run("day", 2 * HOUR), run("hour", 60)]);
+        assert_eq!(ranked(&deps, None), ["hour", "day", "week", "month"]);
+    }
+
+    #[test]
+    fn frequent_old_runs_outrank_a_single_recent_one() {
+        // 3 x 0.5 against 1.0, then 7 x 0.5 against 4.0
+        let mut runs = vec![run("week", 3 * DAY)];
+        runs.extend((0..3).map(|_| run("often", 30 * DAY)));
+        assert_eq!(ranked(&history(&runs), None), ["often", "week"]);
+
+        let mut runs = vec![run("hour", 60)];
+        runs.extend((0..7).map(|_| run("often", 30 * DAY)));
+        assert_eq!(ranked(&history(&runs), None), ["hour", "often"]);
+    }
+
+    #[test]
+    fn failed_runs_count_for_a_quarter() {
+        // 3 failures in the last hour weigh 3.0 against one success at 4.0
+        let mut runs: Vec<Run> = (0..3).map(|_| Run{ exit_code: Some(1), ..run("fails", 60) }).collect();
+        runs.push(run("works", 60));
+        assert_eq!(ranked(&history(&runs), None), ["works", "fails"]);
+
+        // while 5 failures at 5.0 beat it
+        let mut runs: Vec<Run> = (0..5).map(|_| Run{ exit_code: Some(1), ..run("fails", 60) }).collect();
+        runs.push(run("works", 60));
+        assert_eq!(ranked(&history(&runs), None), ["fails", "works"]);
+
+        // an unknown exit code counts as a success
+        let runs = vec![Run{ exit_code: None, ..run("unknown", 60) }, Run{ exit_code: Some(127), ..run("missing", 60) }];
+        assert_eq!(ranked(&history(&runs), None), ["unknown", "missing"]);
+    }
+
+    #[test]
+    fn runs_in_the_search_directory_count_double() {
+        // 2.0 in /proj against 1.0 + 0.5 elsewhere, or 1.0 against 1.5 without a directory
+        let deps = history(&[
+            Run{ cwd: Some("/proj"), ..run("here", 3 * DAY) },
+            Run{ cwd: Some("/other"), ..run("there", 3 * DAY) },
+            Run{ cwd: Some("/other"), ..run("there", 30 * DAY) },
+        ]);
+        assert_eq!(ranked(&deps, Some("/proj")), ["here", "there"]);
+        assert_eq!(ranked(&deps, None), ["there", "here"]);
+    }
+
+    #[test]
+    fn ties_go_to_the_most_recently_recorded_command() {
+        // 4 failures weigh the same as one success, the last one recorded wins
+        let mut runs: Vec<Run> = (0..4).map(|_| Run{ exit_code: Some(1), ..run("failed", 60) }).collect();
+        runs.push(run("passed", 60));
+        assert_eq!(ranked(&history(&runs), None), ["passed", "failed"]);
+
+        let deps = history(&[run("b", DAY + HOUR), run("a", DAY + HOUR), run("b", DAY + HOUR), run("a", DAY + HOUR)]);
+        assert_eq!(ranked(&deps, None), ["a", "b"]);
+        let deps = history(&[run("a", DAY + HOUR), run("b", DAY + HOUR)]);
+        assert_eq!(ranked(&deps, None), ["b", "a"]);
+    }
+}
@@ -0,0 +1,115 @@
+// Scopes narrow a search to history recorded on this host, in this terminal session, in
+// the current directory or anywhere inside the current git repository. Each scope is
+// compared against the metadata stored with the command when it was recorded.
+
+use std::path::{Path, PathBuf};
+
+use rusqlite::types::Value;
+use serde::{Deserialize, Serialize};
+
+use super::super::record;
+use super::{Filter, SearchError};
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Global,
+    Host,
+    Session,
+    #[serde(rename = "cwd", alias = "directory")]
+    Directory,
+    #[serde(rename = "repo", alias = "repository")]
+    Repository,
+}
+
+/// Where the search was started from, scopes without a value here cannot be selected
+pub struct Context {
+    pub host: Option<String>,
+    pub session: Option<String>,
+    pub cwd: Option<String>,
+    /// root of the git repository containing `cwd`
+    pub repository: Option<String>,
+}
+
+/// Walks up from `dir` to the first directory containing `.git`, which is a file in worktrees and submodules
+fn repository_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().find(|dir| dir.join(".git").exists()).map(Path::to_path_buf)
+}
+
+impl Context {
+    /// `session` is passed by the shell widget, falling back to the one exported into the environment
+    pub fn current(session: Option<&str>) -> Self {
+        // PWD keeps symlinked paths as the shell recorded them, current_dir resolves them
+        let cwd = std::env::var("PWD").ok().map(PathBuf::from).filter(|dir| dir.is_absolute())
+            .or_else(|| std::env::current_dir().ok());
+        let repository = cwd.as_deref().and_then(repository_root);
+        Context{
+            host: record::hostname(),
+            session: session.map(str::to_owned).or_else(|| std::env::var("_SCRIBE_SESSION").ok()).filter(|s| !s.is_empty()),
+            cwd: cwd.map(|dir| dir.to_string_lossy().into_owned()),
+            repository: repository.map(|dir| dir.to_string_lossy().into_owned()),
+        }
+    }
+
+    fn value(&self, scope: Scope) -> Option<&str> {
+        match scope {
+            Scope::Global => Some(""),
+            Scope::Host => self.host.as_deref(),
+            Scope::Session => self.session.as_deref(),
+            Scope::Directory => self.cwd.as_deref(),
+            Scope::Repository => self.repository.as_deref(),
+        }
+    }
+}
+
+impl Scope {
+    pub fn parse(name: &str) -> Result<Self, SearchError> {
+        match name {
+            "global" => Ok(Scope::Global),
+            "host" => Ok(Scope::Host),
+            "session" => Ok(Scope::Session),
+            "cwd" | "directory" => Ok(Scope::Directory),
+            "repo" | "repository" => Ok(Scope::Repository),
+            _ => Err(SearchError{ cause: format!("Unknown search scope '{}'", name) }),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scope::Global => "global",
+            Scope::Host => "host",
+            Scope::Session => "session",
+            Scope::Directory => "cwd",
+            Scope::Repository => "repo",
+        }
+    }
+
+    /// The following scope that `context` has a value for, cycling back to global
+    pub fn next(self, context: &Context) -> Self {
+        let order = [Scope::Global, Scope::Host, Scope::Session, Scope::Directory, Scope::Repository];
+        let start = order.iter().position(|scope| *scope == self).unwrap_or(0);
+        order.iter().cycle().skip(start + 1).take(order.len())
+            .find(|scope| context.value(**scope).is_some())
+            .copied()
+            .unwrap_or(Scope::Global)
+    }
+
+    pub fn filter(self, context: &Context) -> Result<Filter, SearchError> {
+        let mut filter = Filter::default();
+        let value = match context.value(self) {
+            Some(value) => Value::Text(value.to_owned()),
+            None => return Err(SearchError{ cause: format!("The '{}' scope is not available here", self.name()) }),
+        };
+        match self {
+            Scope::Global => {}
+            Scope::Host => filter.push("hostname = :scope_host", vec![(":scope_host", value)]),
+            Scope::Session => filter.push("session = :scope_session", vec![(":scope_session", value)]),
+            Scope::Directory => filter.push("cwd = :scope_cwd", vec![(":scope_cwd", value)]),
+            Scope::Repository => filter.push(
+                "cwd = :scope_root OR substr(cwd, 1, length(:scope_root) + 1) = :scope_root || '/'",
+                vec![(":scope_root", value)],
+            ),
+        }
+        Ok(filter)
+    }
+}
@@ -0,0 +1,136 @@
+// Full-screen layout drawn on the alternate screen: a header, the prompt, the result list,
+// a preview of the selected command and a status bar along the bottom.
+
+use std::io::Write;
+
+use termion::{clear, cursor, screen, style};
+
+use super::super::dates;
+use super::super::history::Entry;
+use super::{render_row, Palette, Row, TermSize};
+
+/// Rows taken by the header, prompt, preview separator and status bar
+const CHROME: u16 = 4;
+/// Smallest preview pane, enough for the metadata and the first line of the command
+const MIN_PREVIEW: u16 = 5;
+
+pub struct Frame<'a> {
+    pub prompt: &'a str,
+    pub query: &'a str,
+    pub rows: &'a [Row],
+    /// show how many runs were collapsed into each row
+    pub counts: bool,
+    pub scroll: usize,
+    pub selected: usize,
+    /// active settings shown in the status bar
+    pub status: &'a [String],
+    /// malformed query, shown in place of the matches
+    pub error: Option<&'a str>,
+    pub now: u32,
+    pub palette: &'a Palette,
+}
+
+fn preview_height(rows: u16) -> u16 {
+    (rows / 3).max(MIN_PREVIEW).min(rows.saturating_sub(CHROME + 1))
+}
+
+pub fn list_height(rows: u16) -> u16 {
+    rows.saturating_sub(CHROME + preview_height(rows)).max(1)
+}
+
+/// Leaves the alternate screen before the panic message is printed, otherwise it is lost
+/// when the terminal switches back
+pub fn restore_on_panic() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if let Ok(mut tty) = termion::get_tty() {
+            let _ = write!(tty, "{}{}{}", style::Reset, cursor::Show, screen::ToMainScreen);
+            let _ = tty.flush();
+        }
+        previous(info);
+    }));
+}
+
+/// Draws an inverted full-width bar with `left` and `right` aligned text, `right` is dropped if it does not fit
+fn bar(writer: &mut dyn Write, row: u16, width: usize, left: &str, right: &str) -> std::io::Result<()> {
+    let left: String = left.chars().take(width).collect();
+    let room = width - left.chars().count();
+    let right = if right.chars().count() <= room { format!("{:>room$}", right, room = room) } else { " ".repeat(room) };
+    write!(writer, "{}{}{}{}{}", cursor::Goto(1, row), style::Invert, left, right, style::Reset)
+}
+
+/// Metadata lines followed by the full command, wrapped to the screen width
+fn preview_lines(entry: &Entry, count: u32, now: u32, width: usize) -> Vec<String> {
+    let mut when = format!("when     {} ({} ago)", dates::format_local(entry.timestamp), dates::ago(entry.timestamp, now));
+    if let Some(end) = entry.end_timestamp {
+        when.push_str(&format!("  took {}", dates::ago(entry.timestamp, end)));
+    }
+    if let Some(code) = entry.exit_code {
+        when.push_str(&format!("  exit {}", code));
+    }
+    if count > 1 {
+        when.push_str(&format!("  run {} times", count));
+    }
+
+    let mut lines = vec![
+        when,
+        format!("cwd      {}", entry.cwd.as_deref().unwrap_or("-")),
+        format!(
+            "host     {}  user {}  session {}",
+            entry.hostname.as_deref().unwrap_or("-"), entry.user.as_deref().unwrap_or("-"), entry.session.as_deref().unwrap_or("-"),
+        ),
+        String::new(),
+    ];
+
+    for line in entry.command.split('\n') {
+        let chars: Vec<char> = line.replace('\t', "    ").chars().collect();
+        if chars.is_empty() {
+            lines.push(String::new());
+        }
+        for chunk in chars.chunks(width.max(1)) {
+            lines.push(chunk.iter().collect());
+        }
+    }
+    lines
+}
+
+/// Redraws the whole screen, returning the row the prompt was drawn on
+pub fn draw(writer: &mut dyn Write, size: &TermSize, frame: &Frame) -> std::io::Result<u16> {
+    let width = size.ws_col as usize;
+    let list = list_height(size.ws_row);
+    let preview = preview_height(size.ws_row);
+
+    write!(writer, "{}{}", cursor::Goto(1, 1), clear::All)?;
+    bar(writer, 1, width, " scribe search", "enter accept  ^c cancel  ^t mode  ^x scope  ^o rank ")?;
+
+    let prompt_row = 2;
+    let palette = frame.palette;
+    write!(writer, "{}{}{}{}{}", cursor::Goto(1, prompt_row), palette.prompt, frame.prompt, style::Reset, frame.query)?;
+
+    let top = prompt_row + 1;
+    for (row, line) in frame.rows.iter().skip(frame.scroll).take(list as usize).enumerate() {
+        let line = render_row(line, frame.now, width, frame.scroll + row == frame.selected, frame.counts, palette);
+        write!(writer, "{}{}", cursor::Goto(1, top + row as u16), line)?;
+    }
+    if let Some(error) = frame.error {
+        write!(writer, "{}{}{}{}", cursor::Goto(1, top), palette.error, error, style::Reset)?;
+    } else if frame.rows.is_empty() {
+        write!(writer, "{}{}<no match>{}", cursor::Goto(1, top), palette.muted, style::Reset)?;
+    }
+
+    let separator = top + list;
+    write!(writer, "{}{}{}{}", cursor::Goto(1, separator), palette.muted, "─".repeat(width), style::Reset)?;
+    if let Some(row) = frame.rows.get(frame.selected) {
+        let lines = preview_lines(&row.entry, row.count, frame.now, width);
+        let overflow = lines.len() > preview as usize;
+        for (row, line) in lines.iter().take(preview as usize).enumerate() {
+            write!(writer, "{}{}", cursor::Goto(1, separator + 1 + row as u16), line)?;
+        }
+        if overflow {
+            write!(writer, "{}{}…{}", cursor::Goto(size.ws_col, separator + preview), palette.muted, style::Reset)?;
+        }
+    }
+
+    bar(writer, size.ws_row, width, &format!(" {}", frame.status.join("  |  ")), "")?;
+    Ok(prompt_row)
+}